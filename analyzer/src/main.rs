@@ -1,10 +1,74 @@
-use std::{collections::HashMap, env, error::Error};
+use std::{cmp::Reverse, collections::HashMap, env, error::Error};
 
-use rusqlite::Connection;
+use rusqlite::{Connection, OpenFlags};
+
+/// Max count of applications listed in playtime report
+const PLAYTIME_TOP: usize = 20;
+/// Max count of rows listed per integrity problem
+const INTEGRITY_EXAMPLES: usize = 10;
+/// Max count of messages listed in search results
+const SEARCH_RESULTS: usize = 20;
+/// Max count of tokens in a search result snippet
+const SNIPPET_TOKENS: usize = 16;
+/// Terminal escape codes around search hits, bold and back to normal
+const HIGHLIGHT: (&str, &str) = ("\x1b[1m", "\x1b[0m");
+/// Schema version the reports are written for
+///
+/// the count of `MIGRATIONS` in parser/src/migration.rs, has to change together with them
+const SCHEMA_VERSION: u32 = 2;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let db_path = env::args().nth(1).expect("missing db path");
-    let db_connection = Connection::open(db_path)?;
+    let report = env::args().nth(2).unwrap_or_else(|| "words".to_string());
+    // without the create flag, a wrong path is an error instead of a new empty db
+    let db_connection = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    check_schema_version(&db_connection)?;
+
+    match report.as_str() {
+        "words" => word_count(&db_connection),
+        "playtime" => playtime(&db_connection),
+        "integrity" => integrity(&db_connection),
+        "search" => {
+            let query: Vec<String> = env::args().skip(3).collect();
+            search(&db_connection, &query.join(" "))
+        }
+        _ => Err(format!("unknown report: {}", report).into()),
+    }
+}
+
+/// Refuses dbs written by another parser release, reports would read wrong columns
+///
+/// dbs written before schema versions were tracked have no schema_version table, they are version 1
+fn check_schema_version(db_connection: &Connection) -> Result<(), Box<dyn Error>> {
+    let versioned: bool = db_connection.query_row(
+        "select count(*) > 0 from sqlite_master where type = 'table' and name = 'schema_version';",
+        (),
+        |row| row.get(0),
+    )?;
+    let version: Option<u32> = match versioned {
+        true => db_connection.query_row("select max(version) from schema_version;", (), |row| {
+            row.get(0)
+        })?,
+        false => None,
+    };
+    match version {
+        Some(SCHEMA_VERSION) => Ok(()),
+        Some(version) if version > SCHEMA_VERSION => Err(format!(
+            "db schema version {} is newer than supported version {}, update the analyzer",
+            version, SCHEMA_VERSION
+        )
+        .into()),
+        _ => Err(format!(
+            "db schema version {} is older than supported version {}, \
+            import a package into it with --merge to upgrade",
+            version.unwrap_or(1),
+            SCHEMA_VERSION
+        )
+        .into()),
+    }
+}
+
+fn word_count(db_connection: &Connection) -> Result<(), Box<dyn Error>> {
     let mut stmt = db_connection.prepare(
         r"select contents from message where contents is not null and contents not like '<%>';",
     )?;
@@ -25,9 +89,183 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let mut word_count = word_count.into_iter().collect::<Vec<(String, i32)>>();
 
-    word_count.sort_by(|a, b| b.1.cmp(&a.1));
+    word_count.sort_by_key(|word| Reverse(word.1));
 
     println!("{:#?}", word_count);
 
     Ok(())
 }
+
+/// Ranks games and other applications by total playtime and by last time played
+///
+/// application names are only known for applications owned by the account
+fn playtime(db_connection: &Connection) -> Result<(), Box<dyn Error>> {
+    let mut stmt = db_connection.prepare(
+        r"
+        select coalesce(application.name, application_usage.application_id),
+            application_usage.total_duration, application_usage.last_played_at
+        from application_usage
+        left join application on application.id = application_usage.application_id;
+        ",
+    )?;
+    let usages: Result<Vec<(String, u64, Option<String>)>, rusqlite::Error> = stmt
+        .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect();
+    let mut usages = usages?;
+
+    usages.sort_by_key(|usage| Reverse(usage.1));
+    println!("[Playtime] by total duration");
+    for (rank, (name, total_duration, _)) in usages.iter().take(PLAYTIME_TOP).enumerate() {
+        println!(
+            "{:>3}. {} - {:.1}h",
+            rank + 1,
+            name,
+            *total_duration as f64 / 3600.0
+        );
+    }
+
+    // timestamps are in ISO 8601, so they are ordered as text
+    usages.sort_by(|a, b| b.2.cmp(&a.2));
+    println!("[Playtime] by last played");
+    for (rank, (name, _, last_played_at)) in usages.iter().take(PLAYTIME_TOP).enumerate() {
+        println!(
+            "{:>3}. {} - {}",
+            rank + 1,
+            name,
+            last_played_at.as_deref().unwrap_or("never")
+        );
+    }
+
+    Ok(())
+}
+
+/// Lists rows pointing at rows which do not exist
+///
+/// the parser enforces foreign keys, so orphans come from dbs written by older releases.
+/// channels of servers missing from the package are expected, the account may have left them
+fn integrity(db_connection: &Connection) -> Result<(), Box<dyn Error>> {
+    let checks = [
+        (
+            "messages of unknown channels",
+            r"
+            select message.id || ' in channel ' || message.channel_id from message
+            where message.channel_id not in (select id from channel);
+            ",
+        ),
+        (
+            "recipients of unknown channels",
+            r"
+            select channel_recipient.recipient || ' in channel ' || channel_recipient.channel_id
+            from channel_recipient
+            where channel_recipient.channel_id not in (select id from channel);
+            ",
+        ),
+        (
+            "accepted languages of unknown activities",
+            r"
+            select language || ' of event ' || event_id from accepted_languages as languages
+            where not exists (
+                select * from activity where activity.event_id = languages.event_id
+                    and activity.activity_type is languages.activity_type
+            )
+            union all
+            select language || ' (weighted) of event ' || event_id
+            from accepted_languages_weighted as languages
+            where not exists (
+                select * from activity where activity.event_id = languages.event_id
+                    and activity.activity_type is languages.activity_type
+            );
+            ",
+        ),
+        (
+            "channels of unknown servers",
+            r"
+            select channel.id || ' in server ' || channel.server_id from channel
+            where channel.server_id is not null
+                and channel.server_id not in (select id from server);
+            ",
+        ),
+        (
+            "other foreign key violations",
+            r#"
+            select violation."table" || ' row ' || violation.rowid || ' -> ' || violation.parent
+            from pragma_foreign_key_check as violation
+            where violation."table" not in ('message', 'channel_recipient')
+                and violation."table" not like 'accepted_languages%';
+            "#,
+        ),
+    ];
+
+    for (problem, sql) in checks {
+        let mut stmt = db_connection.prepare(sql)?;
+        let rows: Result<Vec<String>, rusqlite::Error> =
+            stmt.query_map((), |row| row.get(0))?.collect();
+        let rows = rows?;
+        println!("[Integrity] {}: {}", problem, rows.len());
+        for row in rows.iter().take(INTEGRITY_EXAMPLES) {
+            println!("  {}", row);
+        }
+        if rows.len() > INTEGRITY_EXAMPLES {
+            println!("  ...and {} more", rows.len() - INTEGRITY_EXAMPLES);
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds messages matching a full-text `query`, best matches first
+///
+/// the query uses sqlite fts5 syntax: words, "exact phrases", prefixes*, AND, OR, NOT
+fn search(db_connection: &Connection, query: &str) -> Result<(), Box<dyn Error>> {
+    if query.trim().is_empty() {
+        return Err("missing search query".into());
+    }
+    let invalid_query = |err: rusqlite::Error| format!("search for {}: {}", query, err);
+
+    let total: u64 = db_connection
+        .query_row(
+            "select count(*) from message_search where message_search match ?1;",
+            (query,),
+            |row| row.get(0),
+        )
+        .map_err(invalid_query)?;
+    let mut stmt = db_connection.prepare(
+        r"
+        select message.timestamp, coalesce(server.name, channel.server_id, 'direct messages'),
+            coalesce(channel.name, message.channel_id),
+            snippet(message_search, 0, ?2, ?3, '…', ?4)
+        from message_search
+        join message on message.id = cast(message_search.rowid as text)
+        left join channel on channel.id = message.channel_id
+        left join server on server.id = channel.server_id
+        where message_search match ?1
+        order by message_search.rank
+        limit ?5;
+        ",
+    )?;
+    let results: Result<Vec<(String, String, String, String)>, rusqlite::Error> = stmt
+        .query_map(
+            (
+                query,
+                HIGHLIGHT.0,
+                HIGHLIGHT.1,
+                SNIPPET_TOKENS,
+                SEARCH_RESULTS,
+            ),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .map_err(invalid_query)?
+        .collect();
+
+    println!("[Search] {} messages match {}", total, query);
+    for (timestamp, server, channel, snippet) in results.map_err(invalid_query)? {
+        // timestamps are in ISO 8601, the date and time up to minutes are enough here
+        let timestamp = timestamp.get(..16).unwrap_or(&timestamp);
+        println!("  {} {} #{}: {}", timestamp, server, channel, snippet);
+    }
+    if total > SEARCH_RESULTS as u64 {
+        println!("  ...and {} more", total - SEARCH_RESULTS as u64);
+    }
+
+    Ok(())
+}
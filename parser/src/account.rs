@@ -1,4 +1,9 @@
+use std::collections::{BTreeMap, HashMap};
+
 use serde::Deserialize;
+use serde_json::Value;
+
+use crate::channel::string_or_number;
 
 /// Account info
 /// 
@@ -6,6 +11,12 @@ use serde::Deserialize;
 /// - basic user info
 /// - user_profile_metadata
 /// - relationships
+/// - payments, entitlements and gifts
+/// - connections to third-party accounts
+/// - user settings and per server notification settings
+/// - notes about other users, by user id
+/// - playtime statistics per application
+/// - applications, read from account/applications
 #[derive(Debug, Deserialize)]
 pub struct Account {
     pub id: String,
@@ -13,21 +24,43 @@ pub struct Account {
     pub discriminator: i32,
     pub email: String,
     pub verified: bool,
-    pub avatar_hash: String,
+    pub avatar_hash: Option<String>,
+    #[serde(default)]
     pub has_mobile: bool,
+    #[serde(default)]
     pub needs_email_verification: bool,
     pub premium_until: Option<String>,
+    #[serde(default)]
     pub flags: i64,
     pub phone: Option<String>,
     pub temp_banned_until: Option<String>,
     pub ip: String,
+    /// missing in legacy packages
+    #[serde(default)]
     pub user_profile_metadata: UserMetadata,
+    #[serde(default)]
     pub relationships: Vec<Relationship>,
+    #[serde(default)]
+    pub payments: Vec<Payment>,
+    #[serde(default)]
+    pub entitlements: Vec<Entitlement>,
+    #[serde(default)]
+    pub gifts: Vec<Gift>,
+    #[serde(default)]
+    pub connections: Vec<Connection>,
+    pub settings: Option<Settings>,
+    #[serde(default)]
+    pub guild_settings: Vec<GuildSettings>,
+    #[serde(default)]
+    pub notes: BTreeMap<String, String>,
+    #[serde(default)]
+    pub user_activity_application_statistics: Vec<ApplicationStatistics>,
+    #[serde(skip)]
+    pub applications: Vec<Application>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct UserMetadata {
-    pub user_id: String,
     pub boosting_started_at: Option<String>,
     pub premium_started_at: Option<String>,
 }
@@ -47,6 +80,152 @@ pub struct User {
     pub username: String,
     pub avatar: Option<String>,
     pub avatar_decoration: Option<String>,
+    #[serde(deserialize_with = "string_or_number")]
     pub discriminator: String,
+    #[serde(default)]
     pub public_flags: u32,
 }
+
+/// Payment made by the account, amounts are in the smallest currency unit
+#[derive(Debug, Deserialize)]
+pub struct Payment {
+    pub id: String,
+    pub created_at: String,
+    pub currency: String,
+    pub amount: i64,
+    pub amount_refunded: Option<i64>,
+    pub tax: Option<i64>,
+    pub tax_inclusive: Option<bool>,
+    pub status: Option<u32>,
+    pub description: Option<String>,
+    pub sku_id: Option<String>,
+    pub sku_price: Option<i64>,
+    pub sku_subscription_plan_id: Option<String>,
+}
+
+/// Sku granted to the account, e.g. nitro or a boost
+#[derive(Debug, Deserialize)]
+pub struct Entitlement {
+    pub id: String,
+    pub sku_id: String,
+    pub application_id: Option<String>,
+    #[serde(rename(deserialize = "type"))]
+    pub entitlement_type: Option<u32>,
+    pub deleted: Option<bool>,
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+    pub subscription_id: Option<String>,
+}
+
+/// Gift code bought by the account
+#[derive(Debug, Deserialize)]
+pub struct Gift {
+    pub code: String,
+    pub sku_id: Option<String>,
+    pub application_id: Option<String>,
+    pub subscription_plan_id: Option<String>,
+    pub uses: Option<u32>,
+    pub max_uses: Option<u32>,
+    pub redeemed: Option<bool>,
+    pub expires_at: Option<String>,
+}
+
+/// Third-party account linked to the account, e.g. steam, github or spotify
+#[derive(Debug, Deserialize)]
+pub struct Connection {
+    #[serde(rename(deserialize = "type"))]
+    pub connection_type: String,
+    pub id: String,
+    pub name: String,
+    pub visibility: Option<u32>,
+    pub verified: Option<bool>,
+    pub revoked: Option<bool>,
+    pub friend_sync: Option<bool>,
+    pub show_activity: Option<bool>,
+}
+
+/// User settings
+///
+/// contains:
+/// - appearance and privacy settings
+/// - other settings, kept as json
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    pub theme: Option<String>,
+    pub locale: Option<String>,
+    pub status: Option<String>,
+    pub developer_mode: Option<bool>,
+    pub explicit_content_filter: Option<u32>,
+    pub default_guilds_restricted: Option<bool>,
+    #[serde(default)]
+    pub restricted_guilds: Vec<String>,
+    pub friend_source_flags: Option<FriendSourceFlags>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// Who can send friend requests, `all` overrides the other flags
+#[derive(Debug, Deserialize)]
+pub struct FriendSourceFlags {
+    pub all: Option<bool>,
+    pub mutual_friends: Option<bool>,
+    pub mutual_guilds: Option<bool>,
+}
+
+/// Notification settings of a server, `guild_id` is empty for direct messages
+#[derive(Debug, Deserialize)]
+pub struct GuildSettings {
+    pub guild_id: Option<String>,
+    pub muted: Option<bool>,
+    pub mute_config: Option<MuteConfig>,
+    pub message_notifications: Option<u32>,
+    pub suppress_everyone: Option<bool>,
+    pub suppress_roles: Option<bool>,
+    pub mobile_push: Option<bool>,
+    pub hide_muted_channels: Option<bool>,
+    #[serde(default)]
+    pub channel_overrides: Vec<ChannelOverride>,
+}
+
+/// Notification settings of a channel, overriding its server settings
+#[derive(Debug, Deserialize)]
+pub struct ChannelOverride {
+    pub channel_id: String,
+    pub muted: Option<bool>,
+    pub mute_config: Option<MuteConfig>,
+    pub message_notifications: Option<u32>,
+    pub collapsed: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MuteConfig {
+    pub end_time: Option<String>,
+}
+
+/// Playtime of a game or other application, durations are in seconds
+#[derive(Debug, Deserialize)]
+pub struct ApplicationStatistics {
+    pub application_id: String,
+    pub total_duration: u64,
+    pub total_discord_sku_duration: Option<u64>,
+    pub last_played_at: Option<String>,
+}
+
+/// Developer application owned by the account
+///
+/// contains:
+/// - basic application info
+/// - bot user, if the application has one
+#[derive(Debug, Deserialize)]
+pub struct Application {
+    pub id: String,
+    pub name: String,
+    pub icon: Option<String>,
+    pub description: Option<String>,
+    pub summary: Option<String>,
+    pub bot_public: Option<bool>,
+    pub bot_require_code_grant: Option<bool>,
+    pub verify_key: Option<String>,
+    pub flags: Option<i64>,
+    pub bot: Option<User>,
+}
@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
 use serde_json::Value;
 
 /// Activity info
 ///
 /// contains:
 /// - common activity info
+/// - typed info of well-known event types, see `TypedActivity`
 /// - other info
 #[derive(Debug, Deserialize)]
 pub struct Activity {
@@ -19,14 +20,247 @@ pub struct Activity {
     pub client_send_timestamp: String,
     pub client_track_timestamp: String,
     pub timestamp: String,
+    #[serde(skip)]
+    pub typed: Option<TypedActivity>,
     #[serde(flatten)]
     pub other: HashMap<String, Value>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ActivityType {
     Analytics,
     Modeling,
     Reporting,
     Tns,
 }
+
+impl Activity {
+    /// Moves fields of well-known event types from `other` to `typed`
+    ///
+    /// events which do not match their typed struct stay untyped
+    pub fn parse_typed(&mut self) {
+        self.typed = match self.event_type.as_str() {
+            "session_start" | "session_end" => self.take_typed().map(TypedActivity::Session),
+            "send_message" => self.take_typed().map(TypedActivity::SendMessage),
+            "join_voice_channel" | "leave_voice_channel" => {
+                self.take_typed().map(TypedActivity::Voice)
+            }
+            "guild_viewed" => self.take_typed().map(TypedActivity::GuildViewed),
+            "app_opened" => self.take_typed().map(TypedActivity::AppOpened),
+            "notification_clicked" => self.take_typed().map(TypedActivity::NotificationClicked),
+            _ => None,
+        };
+    }
+
+    fn take_typed<T: DeserializeOwned + TypedFields>(&mut self) -> Option<T> {
+        let other = Value::Object(std::mem::take(&mut self.other).into_iter().collect());
+        match T::deserialize(&other) {
+            Ok(mut typed) => {
+                self.other = std::mem::take(typed.other());
+                Some(typed)
+            }
+            Err(_) => {
+                if let Value::Object(other) = other {
+                    self.other = other.into_iter().collect();
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Well-known event, saved to its own table instead of the `other` blob
+#[derive(Debug)]
+pub enum TypedActivity {
+    Session(Session),
+    SendMessage(SendMessage),
+    Voice(Voice),
+    GuildViewed(GuildViewed),
+    AppOpened(AppOpened),
+    NotificationClicked(NotificationClicked),
+}
+
+/// Typed event fields, fields which are not typed are kept in `other`
+trait TypedFields {
+    fn other(&mut self) -> &mut HashMap<String, Value>;
+}
+
+/// session_start and session_end
+#[derive(Debug, Deserialize)]
+pub struct Session {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub os: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub os_version: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub browser: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub device: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub client_version: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub release_channel: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub city: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub country_code: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub region_code: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub time_zone: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub isp: Option<String>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// send_message
+#[derive(Debug, Deserialize)]
+pub struct SendMessage {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub message_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub channel_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub guild_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub channel_type: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub length: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub word_count: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub num_attachments: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_bool")]
+    pub mention_everyone: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_bool")]
+    pub private: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_bool")]
+    pub is_friend: Option<bool>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// join_voice_channel and leave_voice_channel
+#[derive(Debug, Deserialize)]
+pub struct Voice {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub channel_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub guild_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub channel_type: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub rtc_connection_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub voice_state_count: Option<i64>,
+    /// only in leave_voice_channel
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub duration: Option<i64>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// guild_viewed
+#[derive(Debug, Deserialize)]
+pub struct GuildViewed {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub guild_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub guild_size_total: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub guild_num_channels: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub guild_num_text_channels: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub guild_num_voice_channels: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub guild_num_roles: Option<i64>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// app_opened
+#[derive(Debug, Deserialize)]
+pub struct AppOpened {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub os: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub browser: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub device: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub load_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub opened_from: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub theme: Option<String>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// notification_clicked
+#[derive(Debug, Deserialize)]
+pub struct NotificationClicked {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub notif_type: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub notif_user_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub message_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub channel_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub guild_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub channel_type: Option<i64>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+macro_rules! impl_typed_fields {
+    ($($typed:ty),*) => {
+        $(impl TypedFields for $typed {
+            fn other(&mut self) -> &mut HashMap<String, Value> {
+                &mut self.other
+            }
+        })*
+    };
+}
+
+impl_typed_fields!(
+    Session,
+    SendMessage,
+    Voice,
+    GuildViewed,
+    AppOpened,
+    NotificationClicked
+);
+
+// event fields are written by different clients, so the same field
+// can be a number in one event and a string in another
+
+fn lenient_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Null => None,
+        Value::String(val) => Some(val),
+        val => Some(val.to_string()),
+    })
+}
+
+fn lenient_i64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(val) => val.as_i64().or_else(|| val.as_f64().map(|val| val as i64)),
+        Value::String(val) => val.trim_matches('"').parse().ok(),
+        _ => None,
+    })
+}
+
+fn lenient_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(val) => Some(val),
+        Value::String(val) => val.parse().ok(),
+        Value::Number(val) => val.as_i64().map(|val| val != 0),
+        _ => None,
+    })
+}
@@ -0,0 +1,60 @@
+use std::io::Read;
+
+use sha2::{Digest, Sha256};
+
+/// Binary file from the package, e.g. account/avatar.png
+///
+/// contains:
+/// - path inside the package
+/// - mime type, size and sha256 hash
+/// - file content
+#[derive(Debug)]
+pub struct BlobAsset {
+    pub path: String,
+    pub mime_type: &'static str,
+    pub size: u64,
+    pub hash: String,
+    pub content: Vec<u8>,
+}
+
+impl BlobAsset {
+    pub fn read(
+        path: &str,
+        mime_type: &'static str,
+        reader: &mut dyn Read,
+    ) -> std::io::Result<Self> {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        Ok(BlobAsset {
+            path: path.to_string(),
+            mime_type,
+            size: content.len() as u64,
+            hash: sha256(&content),
+            content,
+        })
+    }
+}
+
+/// Mime type of binary files kept as blob assets, `None` for everything else
+pub fn mime_type(path: &str) -> Option<&'static str> {
+    let extension = path.rsplit_once('.')?.1.to_lowercase();
+    let mime_type = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        _ => return None,
+    };
+    Some(mime_type)
+}
+
+/// Hex encoded sha256 of `content`
+pub fn sha256(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
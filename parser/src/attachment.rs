@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::asset::sha256;
+
+/// Attachment url from `Message.attachments`
+///
+/// cdn urls look like `https://cdn.discordapp.com/attachments/<channel id>/<attachment id>/<filename>`,
+/// newer ones are signed with `?ex=<expiry, hex unix time>&is=<issue time>&hm=<signature>`
+#[derive(Debug)]
+pub struct AttachmentUrl<'a> {
+    pub channel_id: &'a str,
+    pub attachment_id: &'a str,
+    pub filename: &'a str,
+    pub extension: Option<&'a str>,
+    pub ex: Option<&'a str>,
+    pub is: Option<&'a str>,
+    pub hm: Option<&'a str>,
+}
+
+impl<'a> AttachmentUrl<'a> {
+    pub fn parse(url: &'a str) -> Option<Self> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let mut segments = path.rsplit('/');
+        let filename = segments.next().filter(|filename| !filename.is_empty())?;
+        let attachment_id = segments.next()?;
+        let channel_id = segments.next()?;
+        if segments.next()? != "attachments" {
+            return None;
+        }
+        let query_param = |name: &str| {
+            query
+                .split('&')
+                .filter_map(|param| param.split_once('='))
+                .find(|(key, _val)| *key == name)
+                .map(|(_key, val)| val)
+        };
+        Some(AttachmentUrl {
+            channel_id,
+            attachment_id,
+            filename,
+            extension: filename
+                .rsplit_once('.')
+                .map(|(_name, extension)| extension),
+            ex: query_param("ex"),
+            is: query_param("is"),
+            hm: query_param("hm"),
+        })
+    }
+
+    /// Unix time after which the signed url stops working
+    pub fn expires_at(&self) -> Option<i64> {
+        i64::from_str_radix(self.ex?, 16).ok()
+    }
+}
+
+/// Attachment file found in the local attachment folder
+///
+/// contains:
+/// - message, attachment id and url it belongs to
+/// - local path, size and sha256 hash
+/// - file content, only if attachments are embedded into the db
+#[derive(Debug)]
+pub struct LocalAttachment {
+    pub message_id: String,
+    pub attachment_id: String,
+    pub url: String,
+    pub local_path: String,
+    pub size: u64,
+    pub hash: String,
+    pub content: Option<Vec<u8>>,
+}
+
+impl LocalAttachment {
+    pub fn read(
+        message_id: &str,
+        url: &AttachmentUrl,
+        signed_url: &str,
+        path: &Path,
+        embed: bool,
+    ) -> io::Result<Self> {
+        let (size, hash, content) = if embed {
+            let content = fs::read(path)?;
+            (content.len() as u64, sha256(&content), Some(content))
+        } else {
+            let mut hasher = Sha256::new();
+            let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+            (size, format!("{:x}", hasher.finalize()), None)
+        };
+        Ok(LocalAttachment {
+            message_id: message_id.to_string(),
+            attachment_id: url.attachment_id.to_string(),
+            url: signed_url.to_string(),
+            local_path: path.to_string_lossy().to_string(),
+            size,
+            hash,
+            content,
+        })
+    }
+}
+
+/// Files of the local attachment folder, by file name
+///
+/// a file matches an attachment url when it is named:
+/// - `<filename>` and placed in a folder named `<attachment id>`, at any depth
+/// - `<attachment id>_<filename>` or `<attachment id>-<filename>`
+///
+/// a file named `<filename>` only is never matched, attachments often share names like image.png
+pub struct LocalAttachmentIndex {
+    files: HashMap<String, Vec<PathBuf>>,
+}
+
+impl LocalAttachmentIndex {
+    pub fn new(dir: &str) -> io::Result<Self> {
+        let mut files: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        let mut folders = vec![PathBuf::from(dir)];
+        while let Some(folder) = folders.pop() {
+            for entry in fs::read_dir(folder)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    folders.push(entry.path());
+                } else {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    files.entry(name).or_default().push(entry.path());
+                }
+            }
+        }
+        files.values_mut().for_each(|paths| paths.sort());
+        Ok(LocalAttachmentIndex { files })
+    }
+
+    pub fn find(&self, url: &AttachmentUrl) -> Option<&Path> {
+        let prefixed = [
+            format!("{}_{}", url.attachment_id, url.filename),
+            format!("{}-{}", url.attachment_id, url.filename),
+        ];
+        if let Some(path) = prefixed
+            .iter()
+            .find_map(|name| self.files.get(name)?.first())
+        {
+            return Some(path);
+        }
+
+        let candidates = self.files.get(url.filename)?;
+        candidates
+            .iter()
+            .find(|path| {
+                path.parent()
+                    .into_iter()
+                    .flat_map(Path::components)
+                    .any(|component| component.as_os_str() == url.attachment_id)
+            })
+            .map(PathBuf::as_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signed_cdn_url() {
+        let url = "https://cdn.discordapp.com/attachments/111/222/photo.final.png\
+            ?ex=65a1b2c3&is=659f3f43&hm=abc123&";
+        let attachment_url = AttachmentUrl::parse(url).unwrap();
+
+        assert_eq!(attachment_url.channel_id, "111");
+        assert_eq!(attachment_url.attachment_id, "222");
+        assert_eq!(attachment_url.filename, "photo.final.png");
+        assert_eq!(attachment_url.extension, Some("png"));
+        assert_eq!(attachment_url.ex, Some("65a1b2c3"));
+        assert_eq!(attachment_url.is, Some("659f3f43"));
+        assert_eq!(attachment_url.hm, Some("abc123"));
+        assert_eq!(attachment_url.expires_at(), Some(0x65a1b2c3));
+    }
+
+    #[test]
+    fn parses_unsigned_cdn_url() {
+        let url = "https://cdn.discordapp.com/attachments/111/222/README";
+        let attachment_url = AttachmentUrl::parse(url).unwrap();
+
+        assert_eq!(attachment_url.filename, "README");
+        assert_eq!(attachment_url.extension, None);
+        assert_eq!(attachment_url.ex, None);
+        assert_eq!(attachment_url.expires_at(), None);
+    }
+
+    #[test]
+    fn ignores_invalid_expiry() {
+        let url = "https://cdn.discordapp.com/attachments/111/222/a.png?ex=not-hex";
+        let attachment_url = AttachmentUrl::parse(url).unwrap();
+
+        assert_eq!(attachment_url.ex, Some("not-hex"));
+        assert_eq!(attachment_url.expires_at(), None);
+    }
+
+    #[test]
+    fn finds_local_files_by_attachment_id() {
+        let paths = [
+            "att/image.png",
+            "att/555/photo.png",
+            "att/other/photo.png",
+            "att/666_image.png",
+        ];
+        let mut files: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            let path = PathBuf::from(path);
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            files.entry(name).or_default().push(path);
+        }
+        let index = LocalAttachmentIndex { files };
+        let find = |url: &str| {
+            let url = AttachmentUrl::parse(url).unwrap();
+            index.find(&url).map(Path::to_path_buf)
+        };
+
+        assert_eq!(
+            find("https://cdn.discordapp.com/attachments/1/555/photo.png"),
+            Some(PathBuf::from("att/555/photo.png"))
+        );
+        assert_eq!(
+            find("https://cdn.discordapp.com/attachments/1/666/image.png"),
+            Some(PathBuf::from("att/666_image.png"))
+        );
+        // the only image.png belongs to an unknown attachment
+        assert_eq!(
+            find("https://cdn.discordapp.com/attachments/1/777/image.png"),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_other_urls() {
+        let urls = [
+            "https://example.com/image.png",
+            "https://cdn.discordapp.com/avatars/111/222/a.png",
+            "https://cdn.discordapp.com/attachments/111/222/",
+            "not a url",
+        ];
+        for url in urls {
+            assert!(AttachmentUrl::parse(url).is_none(), "{}", url);
+        }
+    }
+}
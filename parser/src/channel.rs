@@ -1,12 +1,14 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
 
 use crate::servers::Server;
 
 /// Channel info
-/// 
+///
 /// contains:
 /// - basic channel info
-/// - messages
+///
+/// messages are parsed separately, see `ParserEvent::Messages`
 #[derive(Debug, Deserialize)]
 pub struct Channel {
     pub id: String,
@@ -15,17 +17,63 @@ pub struct Channel {
     pub channel_type: u8,
     pub recipients: Option<Vec<String>>,
     pub guild: Option<Server>,
-    pub messages: Vec<Message>,
 }
 
+/// Message info
+///
+/// can be read from:
+/// - messages.csv
+/// - messages.json (newer packages), where ID is a number and empty fields are `""`
+/// - messages.csv of legacy packages, with lowercase columns and without attachments
 #[derive(Debug, Deserialize)]
 pub struct Message {
-    #[serde(rename(deserialize = "ID"))]
+    #[serde(
+        rename(deserialize = "ID"),
+        alias = "id",
+        deserialize_with = "string_or_number"
+    )]
     pub id: String,
-    #[serde(rename(deserialize = "Timestamp"))]
+    #[serde(rename(deserialize = "Timestamp"), alias = "timestamp")]
     pub timestamp: String,
-    #[serde(rename(deserialize = "Contents"))]
+    #[serde(
+        rename(deserialize = "Contents"),
+        alias = "contents",
+        alias = "Content",
+        alias = "content",
+        default,
+        deserialize_with = "non_empty"
+    )]
     pub contents: Option<String>,
-    #[serde(rename(deserialize = "Attachments"))]
+    #[serde(
+        rename(deserialize = "Attachments"),
+        alias = "attachments",
+        default,
+        deserialize_with = "non_empty"
+    )]
     pub attachments: Option<String>,
 }
+
+impl Message {
+    /// Attachment urls, `attachments` keeps them separated by whitespace
+    pub fn attachment_urls(&self) -> impl Iterator<Item = &str> {
+        self.attachments
+            .iter()
+            .flat_map(|attachments| attachments.split_whitespace())
+    }
+}
+
+pub fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(val) => Ok(val),
+        Value::Number(val) => Ok(val.to_string()),
+        val => Err(serde::de::Error::custom(format!(
+            "expected string or number, got {}",
+            val
+        ))),
+    }
+}
+
+fn non_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let val: Option<String> = Option::deserialize(deserializer)?;
+    Ok(val.filter(|val| !val.is_empty()))
+}
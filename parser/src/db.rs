@@ -1,19 +1,31 @@
-use std::{collections::HashMap, error::Error};
+use std::{
+    error::Error,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
 
 use indicatif::ProgressBar;
-use rusqlite::Connection;
+use rusqlite::{Connection, Params, Transaction};
+use serde_json::Value;
 
 use crate::{
     account::Account,
-    activity::{Activity, ActivityType},
-    channel::Channel,
-    migration::drop_create_tables,
-    parser::ParserResult,
-    servers::Server,
+    activity::{Activity, ActivityType, TypedActivity},
+    asset::BlobAsset,
+    attachment::{AttachmentUrl, LocalAttachment},
+    channel::{Channel, Message},
+    manifest::PackageManifest,
+    migration::{self, drop_tables},
+    parser::{ImportError, ParserEvent},
+    servers::{AuditLogEntry, Server},
+    support::SupportTicket,
 };
 
 type OpResult = Result<(), Box<dyn Error>>;
 
+/// Max count of distinct insert statements kept prepared
+const CACHED_STATEMENTS: usize = 64;
+
 pub struct Dao {
     db_connection: Connection,
 }
@@ -21,6 +33,7 @@ pub struct Dao {
 impl Dao {
     pub fn new(db_name: &str) -> rusqlite::Result<Self> {
         let connection = Connection::open(db_name)?;
+        connection.set_prepared_statement_cache_capacity(CACHED_STATEMENTS);
         Ok(Dao {
             db_connection: connection,
         })
@@ -28,34 +41,98 @@ impl Dao {
 }
 
 impl Dao {
-    pub fn save(&self, data: ParserResult, prog_bar: &ProgressBar) -> OpResult {
-        self.drop_create_tables()?;
-        prog_bar.inc(1);
-        self.save_account(data.account)?;
-        prog_bar.inc(1);
-        self.save_servers(data.servers)?;
-        prog_bar.inc(1);
-        self.save_channels(data.channels)?;
-        prog_bar.inc(1);
-        self.save_activities(data.activities)?;
-        prog_bar.inc(1);
-        Ok(())
+    /// Prepares the db and starts the import transaction, rows are only kept once it is committed
+    ///
+    /// when merging, the schema is upgraded in place before the transaction with foreign keys off,
+    /// as rebuilding a parent table would delete its child rows otherwise.
+    /// without merging, all tables are dropped and created again inside the transaction
+    pub fn begin_import(&self, merge: bool) -> Result<Transaction<'_>, Box<dyn Error>> {
+        if merge {
+            self.db_connection
+                .pragma_update(None, "foreign_keys", false)?;
+            migration::migrate(&self.db_connection)?;
+        }
+        self.db_connection
+            .pragma_update(None, "foreign_keys", true)?;
+        let transaction = self.db_connection.unchecked_transaction()?;
+        if !merge {
+            self.db_connection.execute_batch(&drop_tables())?;
+            migration::migrate(&self.db_connection)?;
+        }
+        // both describe a single import only
+        self.db_connection.execute_batch(
+            "
+            delete from import_error;
+            delete from package_manifest;
+            ",
+        )?;
+        Ok(transaction)
     }
 
-    fn drop_create_tables(&self) -> OpResult {
-        self.db_connection.execute_batch(&drop_create_tables())?;
-        Ok(())
+    /// Saves parser events until the parser drops its sender, returns the time spent saving
+    ///
+    /// rows already in the db are updated, so a newer package is merged into an older one
+    /// and history which discord has since dropped from packages is kept.
+    /// time spent waiting for the parser is not counted
+    pub fn save(
+        &self,
+        events: Receiver<ParserEvent>,
+        prog_bar: &ProgressBar,
+    ) -> Result<Duration, Box<dyn Error>> {
+        prog_bar.inc(1);
+        let mut elapsed = Duration::ZERO;
+        for event in events {
+            let now = Instant::now();
+            match event {
+                ParserEvent::Manifest(manifest) => self.save_manifest(manifest)?,
+                ParserEvent::Account(account) => self.save_account(*account)?,
+                ParserEvent::Servers(servers) => self.save_servers(servers)?,
+                ParserEvent::AuditLog(server_id, audit_log) => {
+                    self.save_audit_log(&server_id, audit_log)?
+                }
+                ParserEvent::Channel(channel) => self.save_channel(channel)?,
+                ParserEvent::SupportTicket(ticket) => self.save_support_ticket(ticket)?,
+                ParserEvent::Messages(channel_id, messages) => {
+                    self.save_messages(&channel_id, messages)?
+                }
+                ParserEvent::Activities(activity_type, activities) => {
+                    self.save_activities(activity_type, activities)?
+                }
+                ParserEvent::LocalAttachments(local_attachments) => {
+                    self.save_local_attachments(local_attachments)?
+                }
+                ParserEvent::BlobAsset(blob_asset) => self.save_blob_asset(blob_asset)?,
+                ParserEvent::ImportError(import_error) => self.save_import_error(import_error)?,
+            }
+            elapsed += now.elapsed();
+        }
+        prog_bar.inc(1);
+        Ok(elapsed)
     }
 
     fn save_account(&self, account: Account) -> OpResult {
-        self.db_connection.execute(
+        self.execute(
             "
             insert into account values (
                 ?1, ?2, ?3, ?4,
                 ?5, ?6, ?7, ?8,
                 ?9, ?10, ?11, ?12,
                 ?13, ?14, ?15
-            );
+            ) on conflict (id) do update set
+                username = excluded.username,
+                discriminator = excluded.discriminator,
+                email = excluded.email,
+                verified = excluded.verified,
+                avatar_hash = excluded.avatar_hash,
+                has_mobile = excluded.has_mobile,
+                needs_email_verification = excluded.needs_email_verification,
+                premium_until = excluded.premium_until,
+                flags = excluded.flags,
+                phone = excluded.phone,
+                temp_banned_until = excluded.temp_banned_until,
+                ip = excluded.ip,
+                boosting_started_at = excluded.boosting_started_at,
+                premium_started_at = excluded.premium_started_at;
             ",
             (
                 account.id.to_string(),
@@ -77,18 +154,41 @@ impl Dao {
         )?;
 
         for relationship in account.relationships.iter() {
-            self.db_connection.execute(
+            // rows imported before user ids were saved have no user id to conflict on,
+            // they are matched by name and replaced
+            self.execute(
+                "
+                delete from relationship
+                where account_id = ?1 and user_id is null and username = ?2 and discriminator = ?3;
+                ",
+                (
+                    account.id.to_string(),
+                    &relationship.user.username,
+                    &relationship.user.discriminator,
+                ),
+            )?;
+            self.execute(
                 "
                 insert into relationship (
-                    account_id, relation_type, nickname, username, 
-                    avatar, avatar_decoration, discriminator, public_flags
+                    account_id, user_id, relation_type, nickname,
+                    username, avatar, avatar_decoration, discriminator,
+                    public_flags
                 ) values (
-                    ?1, ?2, ?3, ?4, 
-                    ?5, ?6, ?7, ?8
-                );
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9
+                ) on conflict (account_id, user_id) do update set
+                    relation_type = excluded.relation_type,
+                    nickname = excluded.nickname,
+                    username = excluded.username,
+                    avatar = excluded.avatar,
+                    avatar_decoration = excluded.avatar_decoration,
+                    discriminator = excluded.discriminator,
+                    public_flags = excluded.public_flags;
                 ",
                 (
                     account.id.to_string(),
+                    relationship.id.to_owned(),
                     relationship.relation_type,
                     relationship.nickname.to_owned(),
                     relationship.user.username.to_owned(),
@@ -99,134 +199,849 @@ impl Dao {
                 ),
             )?;
         }
+
+        for (user_id, note) in account.notes {
+            self.execute(
+                "
+                insert into user_note values (?1, ?2, ?3)
+                on conflict (user_id) do update set
+                    account_id = excluded.account_id,
+                    note = excluded.note;
+                ",
+                (user_id, account.id.to_string(), note),
+            )?;
+        }
+
+        for statistics in account.user_activity_application_statistics {
+            self.execute(
+                "
+                insert into application_usage (
+                    account_id, application_id, total_duration, total_discord_sku_duration,
+                    last_played_at
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5
+                ) on conflict (account_id, application_id) do update set
+                    total_duration = excluded.total_duration,
+                    total_discord_sku_duration = excluded.total_discord_sku_duration,
+                    last_played_at = excluded.last_played_at;
+                ",
+                (
+                    account.id.to_string(),
+                    statistics.application_id,
+                    statistics.total_duration,
+                    statistics.total_discord_sku_duration,
+                    statistics.last_played_at,
+                ),
+            )?;
+        }
+
+        for payment in account.payments {
+            self.execute(
+                "
+                insert into payment values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9, ?10, ?11, ?12,
+                    ?13
+                ) on conflict (id) do update set
+                    account_id = excluded.account_id,
+                    created_at = excluded.created_at,
+                    currency = excluded.currency,
+                    amount = excluded.amount,
+                    amount_refunded = excluded.amount_refunded,
+                    tax = excluded.tax,
+                    tax_inclusive = excluded.tax_inclusive,
+                    status = excluded.status,
+                    description = excluded.description,
+                    sku_id = excluded.sku_id,
+                    sku_price = excluded.sku_price,
+                    sku_subscription_plan_id = excluded.sku_subscription_plan_id;
+                ",
+                (
+                    payment.id,
+                    account.id.to_string(),
+                    payment.created_at,
+                    payment.currency,
+                    payment.amount,
+                    payment.amount_refunded,
+                    payment.tax,
+                    payment.tax_inclusive,
+                    payment.status,
+                    payment.description,
+                    payment.sku_id,
+                    payment.sku_price,
+                    payment.sku_subscription_plan_id,
+                ),
+            )?;
+        }
+
+        for entitlement in account.entitlements {
+            self.execute(
+                "
+                insert into entitlement values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9
+                ) on conflict (id) do update set
+                    account_id = excluded.account_id,
+                    sku_id = excluded.sku_id,
+                    application_id = excluded.application_id,
+                    type = excluded.type,
+                    deleted = excluded.deleted,
+                    starts_at = excluded.starts_at,
+                    ends_at = excluded.ends_at,
+                    subscription_id = excluded.subscription_id;
+                ",
+                (
+                    entitlement.id,
+                    account.id.to_string(),
+                    entitlement.sku_id,
+                    entitlement.application_id,
+                    entitlement.entitlement_type,
+                    entitlement.deleted,
+                    entitlement.starts_at,
+                    entitlement.ends_at,
+                    entitlement.subscription_id,
+                ),
+            )?;
+        }
+
+        for gift in account.gifts {
+            self.execute(
+                "
+                insert into gift values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9
+                ) on conflict (code) do update set
+                    account_id = excluded.account_id,
+                    sku_id = excluded.sku_id,
+                    application_id = excluded.application_id,
+                    subscription_plan_id = excluded.subscription_plan_id,
+                    uses = excluded.uses,
+                    max_uses = excluded.max_uses,
+                    redeemed = excluded.redeemed,
+                    expires_at = excluded.expires_at;
+                ",
+                (
+                    gift.code,
+                    account.id.to_string(),
+                    gift.sku_id,
+                    gift.application_id,
+                    gift.subscription_plan_id,
+                    gift.uses,
+                    gift.max_uses,
+                    gift.redeemed,
+                    gift.expires_at,
+                ),
+            )?;
+        }
+
+        for connection in account.connections {
+            self.execute(
+                "
+                insert into connection (
+                    account_id, type, connection_id, name,
+                    visibility, verified, revoked, friend_sync,
+                    show_activity
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9
+                ) on conflict (account_id, type, connection_id) do update set
+                    name = excluded.name,
+                    visibility = excluded.visibility,
+                    verified = excluded.verified,
+                    revoked = excluded.revoked,
+                    friend_sync = excluded.friend_sync,
+                    show_activity = excluded.show_activity;
+                ",
+                (
+                    account.id.to_string(),
+                    connection.connection_type,
+                    connection.id,
+                    connection.name,
+                    connection.visibility,
+                    connection.verified,
+                    connection.revoked,
+                    connection.friend_sync,
+                    connection.show_activity,
+                ),
+            )?;
+        }
+
+        if let Some(settings) = account.settings {
+            let friend_source_flags = settings.friend_source_flags.as_ref();
+            self.execute(
+                "
+                insert into user_settings values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9, ?10, ?11
+                ) on conflict (account_id) do update set
+                    theme = excluded.theme,
+                    locale = excluded.locale,
+                    status = excluded.status,
+                    developer_mode = excluded.developer_mode,
+                    explicit_content_filter = excluded.explicit_content_filter,
+                    default_guilds_restricted = excluded.default_guilds_restricted,
+                    friend_source_all = excluded.friend_source_all,
+                    friend_source_mutual_friends = excluded.friend_source_mutual_friends,
+                    friend_source_mutual_guilds = excluded.friend_source_mutual_guilds,
+                    other = excluded.other;
+                ",
+                (
+                    account.id.to_string(),
+                    settings.theme,
+                    settings.locale,
+                    settings.status,
+                    settings.developer_mode,
+                    settings.explicit_content_filter,
+                    settings.default_guilds_restricted,
+                    friend_source_flags.and_then(|flags| flags.all),
+                    friend_source_flags.and_then(|flags| flags.mutual_friends),
+                    friend_source_flags.and_then(|flags| flags.mutual_guilds),
+                    serde_json::to_string(&settings.other)?,
+                ),
+            )?;
+
+            for server_id in settings.restricted_guilds {
+                self.execute(
+                    "
+                    insert into restricted_server (account_id, server_id) values (?1, ?2)
+                    on conflict do nothing;
+                    ",
+                    (account.id.to_string(), server_id),
+                )?;
+            }
+        }
+
+        for guild_settings in account.guild_settings {
+            self.execute(
+                "
+                insert into server_settings (
+                    account_id, server_id, muted, mute_end_time,
+                    message_notifications, suppress_everyone, suppress_roles, mobile_push,
+                    hide_muted_channels
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9
+                ) on conflict (account_id, coalesce(server_id, '')) do update set
+                    muted = excluded.muted,
+                    mute_end_time = excluded.mute_end_time,
+                    message_notifications = excluded.message_notifications,
+                    suppress_everyone = excluded.suppress_everyone,
+                    suppress_roles = excluded.suppress_roles,
+                    mobile_push = excluded.mobile_push,
+                    hide_muted_channels = excluded.hide_muted_channels;
+                ",
+                (
+                    account.id.to_string(),
+                    guild_settings.guild_id.to_owned(),
+                    guild_settings.muted,
+                    guild_settings
+                        .mute_config
+                        .and_then(|mute_config| mute_config.end_time),
+                    guild_settings.message_notifications,
+                    guild_settings.suppress_everyone,
+                    guild_settings.suppress_roles,
+                    guild_settings.mobile_push,
+                    guild_settings.hide_muted_channels,
+                ),
+            )?;
+
+            for channel_override in guild_settings.channel_overrides {
+                self.execute(
+                    "
+                    insert into channel_settings (
+                        account_id, server_id, channel_id, muted,
+                        mute_end_time, message_notifications, collapsed
+                    ) values (
+                        ?1, ?2, ?3, ?4,
+                        ?5, ?6, ?7
+                    ) on conflict (account_id, channel_id) do update set
+                        server_id = excluded.server_id,
+                        muted = excluded.muted,
+                        mute_end_time = excluded.mute_end_time,
+                        message_notifications = excluded.message_notifications,
+                        collapsed = excluded.collapsed;
+                    ",
+                    (
+                        account.id.to_string(),
+                        guild_settings.guild_id.to_owned(),
+                        channel_override.channel_id,
+                        channel_override.muted,
+                        channel_override
+                            .mute_config
+                            .and_then(|mute_config| mute_config.end_time),
+                        channel_override.message_notifications,
+                        channel_override.collapsed,
+                    ),
+                )?;
+            }
+        }
+
+        for application in account.applications {
+            self.execute(
+                "
+                insert into application values (
+                    ?1, ?2, ?3, ?4, ?5,
+                    ?6, ?7, ?8, ?9, ?10
+                ) on conflict (id) do update set
+                    account_id = excluded.account_id,
+                    name = excluded.name,
+                    icon = excluded.icon,
+                    description = excluded.description,
+                    summary = excluded.summary,
+                    bot_public = excluded.bot_public,
+                    bot_require_code_grant = excluded.bot_require_code_grant,
+                    verify_key = excluded.verify_key,
+                    flags = excluded.flags;
+                ",
+                (
+                    application.id.to_string(),
+                    account.id.to_string(),
+                    application.name,
+                    application.icon,
+                    application.description,
+                    application.summary,
+                    application.bot_public,
+                    application.bot_require_code_grant,
+                    application.verify_key,
+                    application.flags,
+                ),
+            )?;
+
+            if let Some(bot) = application.bot {
+                self.execute(
+                    "
+                    insert into application_bot values (
+                        ?1, ?2, ?3, ?4,
+                        ?5, ?6
+                    ) on conflict (id) do update set
+                        application_id = excluded.application_id,
+                        username = excluded.username,
+                        avatar = excluded.avatar,
+                        discriminator = excluded.discriminator,
+                        public_flags = excluded.public_flags;
+                    ",
+                    (
+                        bot.id,
+                        application.id,
+                        bot.username,
+                        bot.avatar,
+                        bot.discriminator,
+                        bot.public_flags,
+                    ),
+                )?;
+            }
+        }
         Ok(())
     }
 
     fn save_servers(&self, servers: Vec<Server>) -> OpResult {
-        let servers_value: Vec<String> = servers
-            .iter()
-            .map(|server| vec![str_to_sql_str(&server.id), str_to_sql_str(&server.name)].join(", "))
-            .map(|server| format!("({})", server))
-            .collect();
+        for server in servers {
+            self.execute(
+                "
+                insert into server values (?1, ?2)
+                on conflict (id) do update set name = excluded.name;
+                ",
+                (server.id, server.name),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn save_audit_log(&self, server_id: &str, audit_log: Vec<AuditLogEntry>) -> OpResult {
+        for entry in audit_log {
+            self.execute(
+                "
+                insert into audit_log values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8
+                ) on conflict (id) do nothing;
+                ",
+                (
+                    entry.id,
+                    server_id,
+                    entry.user_id,
+                    entry.target_id,
+                    entry.action_type,
+                    entry.changes.map(|changes| changes.to_string()),
+                    entry.options.map(|options| options.to_string()),
+                    entry.reason,
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn save_channel(&self, channel: Channel) -> OpResult {
+        self.execute(
+            "
+            insert into channel (id, type, server_id, name) values (?1, ?2, ?3, ?4)
+            on conflict (id) do update set
+                type = excluded.type,
+                server_id = coalesce(excluded.server_id, channel.server_id),
+                name = coalesce(excluded.name, channel.name);
+            ",
+            (
+                channel.id.to_string(),
+                channel.channel_type,
+                channel.guild.map(|guild| guild.id),
+                channel.name,
+            ),
+        )?;
 
-        let servers_value = servers_value.join(", ");
-        let sql = format!("insert into server values {};", servers_value);
+        for recipient in channel.recipients.unwrap_or_default() {
+            self.execute(
+                "
+                insert into channel_recipient (channel_id, recipient) values (?1, ?2)
+                on conflict do nothing;
+                ",
+                (&channel.id, recipient),
+            )?;
+        }
 
-        self.db_connection.execute(&sql, ())?;
         Ok(())
     }
 
-    fn save_channels(&self, channels: Vec<Channel>) -> OpResult {
-        for channel in channels {
-            self.db_connection.execute(
+    fn save_messages(&self, channel_id: &str, messages: Vec<Message>) -> OpResult {
+        for message in messages {
+            let row = (
+                &message.id,
+                channel_id,
+                &message.timestamp,
+                &message.contents,
+                &message.attachments,
+            );
+            // an insert which did not conflict tells whether the message is new,
+            // only new messages are indexed here, changed contents are reindexed by a trigger
+            let inserted = self.execute(
                 "
-                insert into channel values (?1, ?2, ?3);
+                insert into message values (?1, ?2, ?3, ?4, ?5)
+                on conflict (id) do nothing;
+                ",
+                row,
+            )? == 1;
+            if !inserted {
+                self.execute(
+                    "
+                    update message set
+                        channel_id = ?2,
+                        timestamp = ?3,
+                        contents = ?4,
+                        attachments = ?5
+                    where id = ?1;
+                    ",
+                    row,
+                )?;
+            } else if let Some(contents) = &message.contents {
+                self.execute(
+                    "insert into message_search (rowid, contents) values (cast(?1 as integer), ?2);",
+                    (&message.id, contents),
+                )?;
+            }
+
+            for url in message.attachment_urls() {
+                let attachment_url = AttachmentUrl::parse(url);
+                let attachment_url = attachment_url.as_ref();
+                self.execute(
+                    "
+                    insert into attachment (
+                        message_id, url, channel_id, attachment_id,
+                        filename, extension, param_ex, param_is,
+                        param_hm, expires_at
+                    ) values (
+                        ?1, ?2, ?3, ?4,
+                        ?5, ?6, ?7, ?8,
+                        ?9, datetime(?10, 'unixepoch')
+                    ) on conflict (message_id, coalesce(attachment_id, url)) do update set
+                        url = excluded.url,
+                        param_ex = excluded.param_ex,
+                        param_is = excluded.param_is,
+                        param_hm = excluded.param_hm,
+                        expires_at = excluded.expires_at;
+                    ",
+                    (
+                        &message.id,
+                        url,
+                        attachment_url.map(|url| url.channel_id),
+                        attachment_url.map(|url| url.attachment_id),
+                        attachment_url.map(|url| url.filename),
+                        attachment_url.and_then(|url| url.extension),
+                        attachment_url.and_then(|url| url.ex),
+                        attachment_url.and_then(|url| url.is),
+                        attachment_url.and_then(|url| url.hm),
+                        attachment_url.and_then(|url| url.expires_at()),
+                    ),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save_local_attachments(&self, local_attachments: Vec<LocalAttachment>) -> OpResult {
+        for local_attachment in local_attachments {
+            self.execute(
+                "
+                insert into local_attachment (
+                    message_id, attachment_id, url, local_path,
+                    size, hash, content
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7
+                ) on conflict (message_id, attachment_id) do update set
+                    url = excluded.url,
+                    local_path = excluded.local_path,
+                    size = excluded.size,
+                    hash = excluded.hash,
+                    content = coalesce(excluded.content, local_attachment.content);
                 ",
                 (
-                    channel.id.to_string(),
-                    channel.channel_type,
-                    channel.guild.map(|guild| guild.id),
+                    local_attachment.message_id,
+                    local_attachment.attachment_id,
+                    local_attachment.url,
+                    local_attachment.local_path,
+                    local_attachment.size,
+                    local_attachment.hash,
+                    local_attachment.content,
                 ),
             )?;
+        }
+        Ok(())
+    }
+
+    fn save_support_ticket(&self, ticket: SupportTicket) -> OpResult {
+        self.execute(
+            "
+            insert into support_ticket values (
+                ?1, ?2, ?3, ?4,
+                ?5, ?6, ?7
+            ) on conflict (id) do update set
+                subject = excluded.subject,
+                description = excluded.description,
+                status = excluded.status,
+                priority = excluded.priority,
+                created_at = excluded.created_at,
+                updated_at = excluded.updated_at;
+            ",
+            (
+                ticket.id.to_string(),
+                ticket.subject,
+                ticket.description,
+                ticket.status,
+                ticket.priority,
+                ticket.created_at,
+                ticket.updated_at,
+            ),
+        )?;
 
-            for message in channel.messages {
-                self.db_connection.execute(
-                    "insert into message values (?1, ?2, ?3, ?4, ?5);",
+        for comment in ticket.comments {
+            let author = comment.author.map(|author| match author {
+                Value::String(author) => author,
+                author => author.to_string(),
+            });
+            self.execute(
+                "
+                insert into support_ticket_comment (
+                    ticket_id, author, comment, public,
+                    created_at
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5
+                ) on conflict do nothing;
+                ",
+                (
+                    ticket.id.to_string(),
+                    author,
+                    comment.comment,
+                    comment.public,
+                    comment.created_at,
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn save_activities(&self, activity_type: ActivityType, activities: Vec<Activity>) -> OpResult {
+        let activity_type = format!("{:?}", activity_type);
+        for activity in activities {
+            self.execute(
+                "
+                insert into activity (
+                    event_id, event_type, activity_type, user_id,
+                    domain, client_send_timestamp, client_track_timestamp, timestamp,
+                    other
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9
+                ) on conflict (event_id, activity_type) do nothing;
+                ",
+                (
+                    &activity.event_id,
+                    activity.event_type,
+                    &activity_type,
+                    activity.user_id,
+                    activity.domain,
+                    activity.client_send_timestamp,
+                    activity.client_track_timestamp,
+                    activity.timestamp,
+                    serde_json::to_string(&activity.other)?,
+                ),
+            )?;
+
+            for accepted_language in activity.accepted_languages {
+                self.execute(
+                    "
+                    insert into accepted_languages (event_id, activity_type, language)
+                    values (?1, ?2, ?3)
+                    on conflict do nothing;
+                    ",
+                    (&activity.event_id, &activity_type, accepted_language),
+                )?;
+            }
+            for accepted_language_weighted in activity.accepted_languages_weighted {
+                self.execute(
+                    "
+                    insert into accepted_languages_weighted (event_id, activity_type, language)
+                    values (?1, ?2, ?3)
+                    on conflict do nothing;
+                    ",
                     (
-                        message.id,
-                        &channel.id,
-                        message.timestamp,
-                        message.contents,
-                        message.attachments,
+                        &activity.event_id,
+                        &activity_type,
+                        accepted_language_weighted,
                     ),
                 )?;
             }
-
-            if let Some(recipients) = channel.recipients {
-                let recipients_value: Vec<String> = recipients
-                    .iter()
-                    .map(|recipient| {
-                        vec![str_to_sql_str(&channel.id), str_to_sql_str(recipient)].join(", ")
-                    })
-                    .map(|recipient| format!("({})", recipient))
-                    .collect();
-
-                let recipients_value = recipients_value.join(", ");
-                let sql = format!(
-                    "insert into channel_recipient (channel_id, recipient) values {};",
-                    recipients_value
-                );
-
-                self.db_connection.execute(&sql, ())?;
+            if let Some(typed) = activity.typed {
+                self.save_typed_activity(&activity.event_id, &activity_type, typed)?;
             }
         }
+        Ok(())
+    }
 
+    fn save_typed_activity(
+        &self,
+        event_id: &str,
+        activity_type: &str,
+        typed: TypedActivity,
+    ) -> OpResult {
+        match typed {
+            TypedActivity::Session(session) => self.execute(
+                "
+                insert into activity_session (
+                    event_id, activity_type, os, os_version,
+                    browser, device, client_version, release_channel,
+                    city, country_code, region_code, time_zone,
+                    isp
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9, ?10, ?11, ?12,
+                    ?13
+                ) on conflict (event_id, activity_type) do nothing;
+                ",
+                (
+                    event_id,
+                    activity_type,
+                    session.os,
+                    session.os_version,
+                    session.browser,
+                    session.device,
+                    session.client_version,
+                    session.release_channel,
+                    session.city,
+                    session.country_code,
+                    session.region_code,
+                    session.time_zone,
+                    session.isp,
+                ),
+            )?,
+            TypedActivity::SendMessage(send_message) => self.execute(
+                "
+                insert into activity_send_message (
+                    event_id, activity_type, message_id, channel_id,
+                    server_id, channel_type, length, word_count,
+                    num_attachments, mention_everyone, private, is_friend
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8,
+                    ?9, ?10, ?11, ?12
+                ) on conflict (event_id, activity_type) do nothing;
+                ",
+                (
+                    event_id,
+                    activity_type,
+                    send_message.message_id,
+                    send_message.channel_id,
+                    send_message.guild_id,
+                    send_message.channel_type,
+                    send_message.length,
+                    send_message.word_count,
+                    send_message.num_attachments,
+                    send_message.mention_everyone,
+                    send_message.private,
+                    send_message.is_friend,
+                ),
+            )?,
+            TypedActivity::Voice(voice) => self.execute(
+                "
+                insert into activity_voice (
+                    event_id, activity_type, channel_id, server_id,
+                    channel_type, rtc_connection_id, voice_state_count, duration
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8
+                ) on conflict (event_id, activity_type) do nothing;
+                ",
+                (
+                    event_id,
+                    activity_type,
+                    voice.channel_id,
+                    voice.guild_id,
+                    voice.channel_type,
+                    voice.rtc_connection_id,
+                    voice.voice_state_count,
+                    voice.duration,
+                ),
+            )?,
+            TypedActivity::GuildViewed(guild_viewed) => self.execute(
+                "
+                insert into activity_guild_viewed (
+                    event_id, activity_type, server_id, size_total,
+                    num_channels, num_text_channels, num_voice_channels, num_roles
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8
+                ) on conflict (event_id, activity_type) do nothing;
+                ",
+                (
+                    event_id,
+                    activity_type,
+                    guild_viewed.guild_id,
+                    guild_viewed.guild_size_total,
+                    guild_viewed.guild_num_channels,
+                    guild_viewed.guild_num_text_channels,
+                    guild_viewed.guild_num_voice_channels,
+                    guild_viewed.guild_num_roles,
+                ),
+            )?,
+            TypedActivity::AppOpened(app_opened) => self.execute(
+                "
+                insert into activity_app_opened (
+                    event_id, activity_type, os, browser,
+                    device, load_id, opened_from, theme
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8
+                ) on conflict (event_id, activity_type) do nothing;
+                ",
+                (
+                    event_id,
+                    activity_type,
+                    app_opened.os,
+                    app_opened.browser,
+                    app_opened.device,
+                    app_opened.load_id,
+                    app_opened.opened_from,
+                    app_opened.theme,
+                ),
+            )?,
+            TypedActivity::NotificationClicked(notification_clicked) => self.execute(
+                "
+                insert into activity_notification_clicked (
+                    event_id, activity_type, notif_type, notif_user_id,
+                    message_id, channel_id, server_id, channel_type
+                ) values (
+                    ?1, ?2, ?3, ?4,
+                    ?5, ?6, ?7, ?8
+                ) on conflict (event_id, activity_type) do nothing;
+                ",
+                (
+                    event_id,
+                    activity_type,
+                    notification_clicked.notif_type,
+                    notification_clicked.notif_user_id,
+                    notification_clicked.message_id,
+                    notification_clicked.channel_id,
+                    notification_clicked.guild_id,
+                    notification_clicked.channel_type,
+                ),
+            )?,
+        };
         Ok(())
     }
 
-    fn save_activities(&self, activities: HashMap<ActivityType, Vec<Activity>>) -> OpResult {
-        for (activity_type, activities) in activities {
-            let mut activity_values = Vec::new();
-            let mut accepted_language_values = Vec::new();
-            let mut accepted_language_weighted_values = Vec::new();
-            for activity in activities {
-                activity_values.push(format!(
-                    "({}, {}, {}, {}, {}, {}, {}, {}, {})",
-                    str_to_sql_str(&activity.event_id),
-                    str_to_sql_str(&activity.event_type),
-                    str_to_sql_str(format!("{:?}", activity_type).as_str()),
-                    str_to_sql_str(&activity.user_id),
-                    str_to_sql_str(&activity.domain),
-                    str_to_sql_str(&activity.client_send_timestamp),
-                    str_to_sql_str(&activity.client_track_timestamp),
-                    str_to_sql_str(&activity.timestamp),
-                    str_to_sql_str(serde_json::to_string(&activity.other)?.as_str()),
-                ));
-
-                for accepted_language in activity.accepted_languages {
-                    accepted_language_values.push(format!(
-                        "({}, {})",
-                        str_to_sql_str(&activity.event_id),
-                        str_to_sql_str(&accepted_language)
-                    ));
-                }
-                for accepted_language_weighted in activity.accepted_languages_weighted {
-                    accepted_language_weighted_values.push(format!(
-                        "({}, {})",
-                        str_to_sql_str(&activity.event_id),
-                        str_to_sql_str(&accepted_language_weighted)
-                    ));
-                }
-            }
-            self.execute_batch(
-                "insert into activity (
-                    event_id, event_type, activity_type, user_id, 
-                    domain, client_send_timestamp, 
-                    client_track_timestamp, timestamp, other
-                )",
-                activity_values,
-            )?;
-            self.execute_batch(
-                "insert into accepted_languages (event_id, language)",
-                accepted_language_values,
-            )?;
-            self.execute_batch(
-                "insert into accepted_languages_weighted (event_id, language)",
-                accepted_language_weighted_values,
+    fn save_manifest(&self, manifest: PackageManifest) -> OpResult {
+        for section in manifest.sections {
+            self.execute(
+                "
+                insert into package_manifest (path, format, layout, file_count, known)
+                values (?1, ?2, ?3, ?4, ?5);
+                ",
+                (
+                    section.path,
+                    section.format,
+                    section.layout,
+                    section.file_count,
+                    section.known,
+                ),
             )?;
         }
+        Ok(())
+    }
 
+    fn save_blob_asset(&self, blob_asset: BlobAsset) -> OpResult {
+        self.execute(
+            "
+            insert into blob_asset values (?1, ?2, ?3, ?4, ?5)
+            on conflict (path) do update set
+                mime_type = excluded.mime_type,
+                size = excluded.size,
+                hash = excluded.hash,
+                content = excluded.content;
+            ",
+            (
+                blob_asset.path,
+                blob_asset.mime_type,
+                blob_asset.size,
+                blob_asset.hash,
+                blob_asset.content,
+            ),
+        )?;
         Ok(())
     }
 
-    fn execute_batch(&self, insert: &str, values: Vec<String>) -> OpResult {
-        let values = values.join(",\n");
-        let sql = format!("{} values {}", insert, values);
-        self.db_connection.execute(&sql, ())?;
+    fn save_import_error(&self, import_error: ImportError) -> OpResult {
+        self.execute(
+            "insert into import_error (path, line, error) values (?1, ?2, ?3);",
+            (import_error.path, import_error.line, import_error.error),
+        )?;
         Ok(())
     }
-}
 
-fn str_to_sql_str(val: &str) -> String {
-    format!("'{}'", val.replace('\'', "''"))
+    /// Counts of import errors per file, files with the most errors first
+    pub fn import_error_summary(&self) -> rusqlite::Result<Vec<(String, u64)>> {
+        let mut stmt = self.db_connection.prepare(
+            "
+            select path, count(*) from import_error
+            group by path order by count(*) desc, path;
+            ",
+        )?;
+        let summary = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+        summary
+    }
+
+    /// Executes `sql` with a prepared statement, reused by the following calls
+    fn execute<P: Params>(&self, sql: &str, params: P) -> rusqlite::Result<usize> {
+        self.db_connection.prepare_cached(sql)?.execute(params)
+    }
 }
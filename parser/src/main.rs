@@ -1,35 +1,110 @@
-use std::{env, error::Error, time::Instant};
+use std::{env, error::Error, sync::mpsc, thread, time::Instant};
 
 use indicatif::ProgressBar;
 
-use crate::{db::Dao, parser::Parser};
+use crate::{
+    db::Dao,
+    parser::{Parser, ParserOptions},
+};
 
 mod account;
 mod activity;
+mod asset;
+mod attachment;
 mod channel;
 mod db;
+mod manifest;
 mod migration;
+mod package;
 mod parser;
 mod servers;
+mod support;
+
+/// Max count of parsed chunks waiting to be saved
+const PENDING_CHUNKS: usize = 4;
+/// Max count of files listed in the import error summary
+const SUMMARY_FILES: usize = 10;
+/// Options without a value, `--attachments={path}` is checked by its prefix
+const FLAGS: [&str; 3] = ["--lenient", "--embed-attachments", "--merge"];
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let dis_data_path = env::args().nth(1).expect("missing discord data path");
-    let db_path = env::args().nth(2).expect("missing db path");
-    let prog_bar = ProgressBar::new(9);
+    let args: Vec<String> = env::args().skip(1).collect();
+    // a mistyped option would silently fall back to the default, e.g. recreating a db to merge into
+    if let Some(option) = args.iter().find(|arg| {
+        arg.starts_with("--")
+            && !FLAGS.contains(&arg.as_str())
+            && !arg.starts_with("--attachments=")
+    }) {
+        return Err(format!(
+            "unknown option {}, options are {} and --attachments={{path}}",
+            option,
+            FLAGS.join(", ")
+        )
+        .into());
+    }
+    let lenient = args.iter().any(|arg| arg == "--lenient");
+    let attachments_path = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--attachments="))
+        .map(|path| path.to_string());
+    let embed_attachments = args.iter().any(|arg| arg == "--embed-attachments");
+    let merge = args.iter().any(|arg| arg == "--merge");
+    let mut paths = args.iter().filter(|arg| !arg.starts_with("--"));
+    let dis_data_path = paths.next().expect("missing discord data path");
+    let db_path = paths.next().expect("missing db path");
+    if let Some(arg) = paths.next() {
+        return Err(format!("unexpected argument {}", arg).into());
+    }
+    let prog_bar = ProgressBar::new(6);
+
+    let parser = Parser::new(
+        dis_data_path,
+        ParserOptions {
+            lenient,
+            attachments_path,
+            embed_attachments,
+        },
+    )?;
+    let dao = Dao::new(db_path)?;
+    let transaction = dao.begin_import(merge)?;
+    let (sender, receiver) = mpsc::sync_channel(PENDING_CHUNKS);
 
     let now = Instant::now();
-    let parser = Parser::new(&dis_data_path);
-    let parser_result = parser.parse(&prog_bar)?;
-    let parsing_elapsed = now.elapsed();
+    let saving_elapsed = thread::scope(|scope| -> Result<_, Box<dyn Error>> {
+        let parsing = scope.spawn(|| -> Result<_, String> {
+            parser
+                .parse(&sender, &prog_bar)
+                .map_err(|err| err.to_string())?;
+            drop(sender);
+            Ok(())
+        });
+        let saving = dao.save(receiver, &prog_bar);
+        parsing.join().map_err(|_| "parser thread panicked")??;
+        saving
+    })?;
+    let commit = Instant::now();
+    transaction.commit()?;
+    let saving_elapsed = saving_elapsed + commit.elapsed();
 
-    let dao = Dao::new(&db_path)?;
+    // the package is parsed while the db saves it, so parsing alone is not timed
+    println!("[Saving] Elapsed {:.2?}", saving_elapsed);
+    println!("[Total] Elapsed {:.2?}", now.elapsed());
 
-    let now = Instant::now();
-    dao.save(parser_result, &prog_bar)?;
-    let elapsed = now.elapsed();
-    
-    println!("[Parsing] Elapsed {:.2?}", parsing_elapsed);
-    println!("[Saving] Elapsed {:.2?}", elapsed);
+    if lenient {
+        let summary = dao.import_error_summary()?;
+        let total: u64 = summary.iter().map(|(_path, count)| count).sum();
+        println!(
+            "[Errors] {} skipped in {} files, see import_error table",
+            total,
+            summary.len()
+        );
+        for (path, count) in summary.iter().take(SUMMARY_FILES) {
+            println!("  {}: {}", path, count);
+        }
+        if summary.len() > SUMMARY_FILES {
+            println!("  ...and {} more files", summary.len() - SUMMARY_FILES);
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Folders of the package the parser reads files of
+const KNOWN_SECTIONS: [&str; 9] = [
+    "account",
+    "account/applications",
+    "activity/analytics",
+    "activity/modeling",
+    "activity/reporting",
+    "activity/tns",
+    "messages",
+    "servers",
+    "support_tickets",
+];
+
+/// Sections present in a package, detected from its file list
+///
+/// packages from different years differ in folders and file formats,
+/// so the parser only reads sections listed here
+#[derive(Debug, Clone)]
+pub struct PackageManifest {
+    pub sections: Vec<Section>,
+}
+
+/// Package folder
+///
+/// contains:
+/// - path inside the package, e.g. activity/analytics
+/// - formats of its data files, e.g. json+json.gz, for messages only of the message files
+/// - layout, `current` or `legacy`, for sections laid out differently in older packages
+/// - count of all files in it, including nested folders
+/// - whether the parser reads files of the folder, unknown ones are top level folders only
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub path: String,
+    pub format: Option<String>,
+    pub layout: Option<&'static str>,
+    pub file_count: u64,
+    pub known: bool,
+}
+
+impl PackageManifest {
+    /// Detects sections from `files`, paths of all files in the package
+    pub fn detect(files: &[String]) -> Self {
+        let mut folders: BTreeMap<String, (BTreeSet<&'static str>, u64)> = BTreeMap::new();
+        for file in files {
+            let mut folder_paths: Vec<&str> = KNOWN_SECTIONS
+                .iter()
+                .copied()
+                .filter(|section| is_inside(file, section))
+                .collect();
+            // top level folders are listed even when the parser does not know them
+            let top_level = file.split_once('/').map(|(folder, _)| folder);
+            if let Some(top_level) = top_level.filter(|folder| !folder_paths.contains(folder)) {
+                folder_paths.push(top_level);
+            }
+            for folder_path in folder_paths {
+                let (formats, file_count) = folders.entry(folder_path.to_string()).or_default();
+                formats.extend(data_format(folder_path, file));
+                *file_count += 1;
+            }
+        }
+
+        let sections = folders
+            .into_iter()
+            .map(|(path, (formats, file_count))| Section {
+                known: KNOWN_SECTIONS.contains(&path.as_str()),
+                format: match formats.is_empty() {
+                    true => None,
+                    false => Some(formats.into_iter().collect::<Vec<_>>().join("+")),
+                },
+                layout: layout(&path, files),
+                path,
+                file_count,
+            })
+            .collect();
+        PackageManifest { sections }
+    }
+
+    /// Whether the package has the folder at `path`
+    pub fn has(&self, path: &str) -> bool {
+        self.sections.iter().any(|section| section.path == path)
+    }
+}
+
+fn is_inside(file: &str, folder: &str) -> bool {
+    file.strip_prefix(folder)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Format of data files read by the parser, `None` for images and other files
+///
+/// messages are either in messages.csv or in messages.json, other files of channels do not count
+fn data_format(folder: &str, file: &str) -> Option<&'static str> {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    if folder == "messages" && !name.starts_with("messages.") {
+        None
+    } else if name.ends_with(".json.gz") {
+        Some("json.gz")
+    } else if name.ends_with(".json") {
+        Some("json")
+    } else if name.ends_with(".csv") {
+        Some("csv")
+    } else {
+        None
+    }
+}
+
+/// Layout of sections which older packages lay out differently, `None` for other sections
+///
+/// legacy packages name channel folders `<id>` instead of `c<id>`,
+/// and some have no messages/index.json or servers/index.json
+fn layout(folder: &str, files: &[String]) -> Option<&'static str> {
+    let has_file = |path: &str| files.iter().any(|file| file == path);
+    let legacy = match folder {
+        "messages" => {
+            !has_file("messages/index.json")
+                || files
+                    .iter()
+                    .filter_map(|file| file.strip_prefix("messages/")?.split_once('/'))
+                    .any(|(channel_folder, _)| !channel_folder.starts_with('c'))
+        }
+        "servers" => !has_file("servers/index.json"),
+        _ => return None,
+    };
+    match legacy {
+        true => Some("legacy"),
+        false => Some("current"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(files: &[&str]) -> PackageManifest {
+        let files: Vec<String> = files.iter().map(|file| file.to_string()).collect();
+        PackageManifest::detect(&files)
+    }
+
+    fn section<'a>(manifest: &'a PackageManifest, path: &str) -> &'a Section {
+        manifest
+            .sections
+            .iter()
+            .find(|section| section.path == path)
+            .unwrap_or_else(|| panic!("missing section {}", path))
+    }
+
+    #[test]
+    fn detects_known_and_unknown_sections() {
+        let manifest = detect(&[
+            "README.txt",
+            "account/user.json",
+            "account/avatar.png",
+            "account/applications/1/application.json",
+            "activity/analytics/events-2021-00000-of-00002.json",
+            "activity/analytics/events-2021-00001-of-00002.json.gz",
+            "activity/tns/events-2021-00000-of-00001.json",
+            "programs/programs.json",
+        ]);
+
+        let paths: Vec<&str> = manifest
+            .sections
+            .iter()
+            .map(|section| section.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "account",
+                "account/applications",
+                "activity",
+                "activity/analytics",
+                "activity/tns",
+                "programs"
+            ]
+        );
+
+        let account = section(&manifest, "account");
+        assert!(account.known);
+        assert_eq!(account.file_count, 3);
+        assert_eq!(account.format.as_deref(), Some("json"));
+        assert_eq!(account.layout, None);
+
+        let analytics = section(&manifest, "activity/analytics");
+        assert!(analytics.known);
+        assert_eq!(analytics.format.as_deref(), Some("json+json.gz"));
+
+        assert!(!section(&manifest, "activity").known);
+        assert!(!section(&manifest, "programs").known);
+        assert!(manifest.has("activity/tns"));
+        assert!(!manifest.has("activity/modeling"));
+        assert!(!manifest.has("messages"));
+    }
+
+    #[test]
+    fn detects_message_format_and_layout() {
+        let current = detect(&[
+            "messages/index.json",
+            "messages/c1/channel.json",
+            "messages/c1/messages.csv",
+            "messages/c2/channel.json",
+            "messages/c2/messages.json",
+            "servers/index.json",
+            "servers/3/guild.json",
+        ]);
+        let messages = section(&current, "messages");
+        assert_eq!(messages.format.as_deref(), Some("csv+json"));
+        assert_eq!(messages.layout, Some("current"));
+        assert_eq!(messages.file_count, 5);
+        assert_eq!(section(&current, "servers").layout, Some("current"));
+
+        let legacy = detect(&[
+            "messages/1/channel.json",
+            "messages/1/messages.csv",
+            "servers/3/guild.json",
+        ]);
+        let messages = section(&legacy, "messages");
+        assert_eq!(messages.format.as_deref(), Some("csv"));
+        assert_eq!(messages.layout, Some("legacy"));
+        assert_eq!(section(&legacy, "servers").layout, Some("legacy"));
+
+        // an index alone does not make channel folders without the prefix current
+        let mixed = detect(&["messages/index.json", "messages/1/messages.json"]);
+        assert_eq!(section(&mixed, "messages").layout, Some("legacy"));
+    }
+}
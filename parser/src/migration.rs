@@ -1,16 +1,21 @@
-pub fn drop_create_tables() -> String {
-    "
-        begin;
-        drop table if exists accepted_languages;
-        drop table if exists accepted_languages_weighted;
-        drop table if exists account;
-        drop table if exists relationship;
-        drop table if exists channel;
-        drop table if exists channel_recipient;
-        drop table if exists message;
-        drop table if exists activity;
-        drop table if exists server;
-        
+use std::error::Error;
+
+use rusqlite::Connection;
+
+type BoxErrorResult<T> = Result<T, Box<dyn Error>>;
+
+/// Schema changes in release order, a db at schema version N has the first N of them applied
+///
+/// released migrations are never edited, every schema change is a new migration
+const MIGRATIONS: [&str; 2] = [INITIAL_SCHEMA, FULL_PACKAGE_SCHEMA];
+
+/// Schema version of dbs written by this release
+///
+/// the analyzer keeps a copy, `SCHEMA_VERSION` in analyzer/src/main.rs, which changes with it
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+/// Account, servers, channels, messages and activities
+const INITIAL_SCHEMA: &str = "
         create table account (
             id text primary key not null,
             username text not null,
@@ -85,7 +90,681 @@ pub fn drop_create_tables() -> String {
             language text not null,
             foreign key (event_id) references activity (id) on delete cascade
         );
-        commit;
+";
+
+/// Everything else from the package: account details, audit logs, attachments,
+/// support tickets, typed activities and binary files, with unique keys for merging packages
+/// and a full-text index over message contents
+///
+/// message ids are snowflakes, so they are used as rowids of the index.
+/// new messages are indexed by the parser, an fts insert in a trigger is much slower,
+/// triggers only handle the rare changed or deleted messages
+const FULL_PACKAGE_SCHEMA: &str = "
+        -- nullable, legacy packages have accounts without an avatar
+        create table account_new (
+            id text primary key not null,
+            username text not null,
+            discriminator integer,
+            email text not null,
+            verified boolean not null,
+            avatar_hash text,
+            has_mobile boolean not null,
+            needs_email_verification boolean not null,
+            premium_until text,
+            flags integer not null,
+            phone text,
+            temp_banned_until text,
+            ip text not null,
+            boosting_started_at text,
+            premium_started_at text
+        );
+        insert into account_new select * from account;
+        drop table account;
+        alter table account_new rename to account;
+        -- null for relationships imported before user ids were saved
+        alter table relationship add column user_id text;
+        -- rows are unique by their discord ids, so another package can be merged into the db
+        create unique index relationship_user on relationship (account_id, user_id);
+        create unique index channel_recipient_recipient on channel_recipient (channel_id, recipient);
+        -- null for channels of legacy packages without messages/index.json
+        alter table channel add column name text;
+        -- messages are looked up by channel, also when a channel is deleted
+        create index message_channel on message (channel_id);
+        create table activity_new(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            event_type text not null,
+            activity_type text not null,
+            user_id text not null, -- not a foreign key, the account may be missing from the package
+            domain text not null,
+            client_send_timestamp text not null,
+            client_track_timestamp text not null,
+            timestamp text not null,
+            other blob not null
+        );
+        insert into activity_new select * from activity;
+        drop table activity;
+        alter table activity_new rename to activity;
+        -- an event can be listed in several activity categories, it is kept once per category
+        create unique index activity_event on activity (event_id, activity_type);
+        -- languages reference the activity of their category instead of the integer id
+        create table accepted_languages_new(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            activity_type text, -- null for languages of unknown events in older dbs
+            language text not null,
+            foreign key (event_id, activity_type)
+                references activity (event_id, activity_type) on delete cascade,
+            unique (event_id, activity_type, language)
+        );
+        -- older dbs keep languages by event id only, they are copied to every category of the event
+        insert into accepted_languages_new (event_id, activity_type, language)
+            select distinct accepted_languages.event_id, activity.activity_type, language
+            from accepted_languages
+            left join activity on activity.event_id = accepted_languages.event_id;
+        drop table accepted_languages;
+        alter table accepted_languages_new rename to accepted_languages;
+        create table accepted_languages_weighted_new(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            activity_type text, -- null for languages of unknown events in older dbs
+            language text not null,
+            foreign key (event_id, activity_type)
+                references activity (event_id, activity_type) on delete cascade,
+            unique (event_id, activity_type, language)
+        );
+        insert into accepted_languages_weighted_new (event_id, activity_type, language)
+            select distinct accepted_languages_weighted.event_id, activity.activity_type, language
+            from accepted_languages_weighted
+            left join activity on activity.event_id = accepted_languages_weighted.event_id;
+        drop table accepted_languages_weighted;
+        alter table accepted_languages_weighted_new rename to accepted_languages_weighted;
+        create table user_note(
+            user_id text primary key not null,
+            account_id text not null,
+            note text not null,
+            foreign key (account_id) references account (id) on delete cascade
+        );
+        create table application_usage(
+            id integer not null primary key autoincrement,
+            account_id text not null,
+            application_id text not null,
+            total_duration integer not null, -- in seconds
+            total_discord_sku_duration integer,
+            last_played_at text,
+            foreign key (account_id) references account (id) on delete cascade,
+            unique (account_id, application_id)
+        );
+        create table payment(
+            id text primary key not null,
+            account_id text not null,
+            created_at text not null,
+            currency text not null,
+            amount integer not null, -- in the smallest currency unit, e.g. cents
+            amount_refunded integer,
+            tax integer,
+            tax_inclusive boolean,
+            status integer,
+            description text,
+            sku_id text,
+            sku_price integer,
+            sku_subscription_plan_id text,
+            foreign key (account_id) references account (id) on delete cascade
+        );
+        create table entitlement(
+            id text primary key not null,
+            account_id text not null,
+            sku_id text not null,
+            application_id text,
+            type integer,
+            deleted boolean,
+            starts_at text,
+            ends_at text,
+            subscription_id text,
+            foreign key (account_id) references account (id) on delete cascade
+        );
+        create table gift(
+            code text primary key not null,
+            account_id text not null,
+            sku_id text,
+            application_id text,
+            subscription_plan_id text,
+            uses integer,
+            max_uses integer,
+            redeemed boolean,
+            expires_at text,
+            foreign key (account_id) references account (id) on delete cascade
+        );
+        create table connection(
+            id integer not null primary key autoincrement,
+            account_id text not null,
+            type text not null,
+            connection_id text not null, -- account id on the connected service
+            name text not null,
+            visibility integer,
+            verified boolean,
+            revoked boolean,
+            friend_sync boolean,
+            show_activity boolean,
+            foreign key (account_id) references account (id) on delete cascade,
+            unique (account_id, type, connection_id)
+        );
+        create table user_settings(
+            account_id text primary key not null,
+            theme text,
+            locale text,
+            status text,
+            developer_mode boolean,
+            explicit_content_filter integer,
+            default_guilds_restricted boolean,
+            friend_source_all boolean,
+            friend_source_mutual_friends boolean,
+            friend_source_mutual_guilds boolean,
+            other blob not null,
+            foreign key (account_id) references account (id) on delete cascade
+        );
+        create table restricted_server(
+            id integer not null primary key autoincrement,
+            account_id text not null,
+            server_id text not null, -- not a foreign key, the account may have left the server
+            foreign key (account_id) references account (id) on delete cascade,
+            unique (account_id, server_id)
+        );
+        create table server_settings(
+            id integer not null primary key autoincrement,
+            account_id text not null,
+            server_id text, -- null for direct messages
+            muted boolean,
+            mute_end_time text,
+            message_notifications integer,
+            suppress_everyone boolean,
+            suppress_roles boolean,
+            mobile_push boolean,
+            hide_muted_channels boolean,
+            foreign key (account_id) references account (id) on delete cascade
+        );
+        create table channel_settings(
+            id integer not null primary key autoincrement,
+            account_id text not null,
+            server_id text, -- null for direct messages
+            channel_id text not null,
+            muted boolean,
+            mute_end_time text,
+            message_notifications integer,
+            collapsed boolean,
+            foreign key (account_id) references account (id) on delete cascade,
+            unique (account_id, channel_id)
+        );
+        create table application(
+            id text primary key not null,
+            account_id text not null,
+            name text not null,
+            icon text,
+            description text,
+            summary text,
+            bot_public boolean,
+            bot_require_code_grant boolean,
+            verify_key text,
+            flags integer,
+            foreign key (account_id) references account (id) on delete cascade
+        );
+        create table application_bot(
+            id text primary key not null,
+            application_id text not null,
+            username text not null,
+            avatar text,
+            discriminator text not null,
+            public_flags integer not null,
+            foreign key (application_id) references application (id) on delete cascade
+        );
+        create table audit_log(
+            id text primary key not null,
+            server_id text not null,
+            user_id text,
+            target_id text,
+            action_type integer not null,
+            changes blob,
+            options blob,
+            reason text,
+            foreign key (server_id) references server (id) on delete cascade
+        );
+        create table attachment(
+            id integer not null primary key autoincrement,
+            message_id text not null,
+            url text not null,
+            channel_id text, -- url parts are null for urls which are not discord cdn ones
+            attachment_id text,
+            filename text,
+            extension text,
+            param_ex text, -- cdn signature query params
+            param_is text,
+            param_hm text,
+            expires_at text, -- decoded param_ex
+            foreign key (message_id) references message (id) on delete cascade
+        );
+        create table local_attachment(
+            id integer not null primary key autoincrement,
+            message_id text not null,
+            attachment_id text not null,
+            url text not null, -- url as signed in the latest package
+            local_path text not null,
+            size integer not null,
+            hash text not null, -- sha256, hex encoded
+            content blob, -- only with --embed-attachments
+            foreign key (message_id) references message (id) on delete cascade,
+            unique (message_id, attachment_id)
+        );
+        create table support_ticket(
+            id text primary key not null,
+            subject text,
+            description text,
+            status text,
+            priority text,
+            created_at text,
+            updated_at text
+        );
+        create table support_ticket_comment(
+            id integer not null primary key autoincrement,
+            ticket_id text not null,
+            author text,
+            comment text,
+            public boolean,
+            created_at text,
+            foreign key (ticket_id) references support_ticket (id) on delete cascade
+        );
+        create table activity_session(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            activity_type text not null,
+            os text,
+            os_version text,
+            browser text,
+            device text,
+            client_version text,
+            release_channel text,
+            city text,
+            country_code text,
+            region_code text,
+            time_zone text,
+            isp text,
+            foreign key (event_id, activity_type)
+                references activity (event_id, activity_type) on delete cascade,
+            unique (event_id, activity_type)
+        );
+        create table activity_send_message(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            activity_type text not null,
+            message_id text,
+            channel_id text,
+            server_id text,
+            channel_type integer,
+            length integer,
+            word_count integer,
+            num_attachments integer,
+            mention_everyone boolean,
+            private boolean,
+            is_friend boolean,
+            foreign key (event_id, activity_type)
+                references activity (event_id, activity_type) on delete cascade,
+            unique (event_id, activity_type)
+        );
+        create table activity_voice(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            activity_type text not null,
+            channel_id text,
+            server_id text,
+            channel_type integer,
+            rtc_connection_id text,
+            voice_state_count integer,
+            duration integer, -- only when leaving a voice channel
+            foreign key (event_id, activity_type)
+                references activity (event_id, activity_type) on delete cascade,
+            unique (event_id, activity_type)
+        );
+        create table activity_guild_viewed(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            activity_type text not null,
+            server_id text,
+            size_total integer,
+            num_channels integer,
+            num_text_channels integer,
+            num_voice_channels integer,
+            num_roles integer,
+            foreign key (event_id, activity_type)
+                references activity (event_id, activity_type) on delete cascade,
+            unique (event_id, activity_type)
+        );
+        create table activity_app_opened(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            activity_type text not null,
+            os text,
+            browser text,
+            device text,
+            load_id text,
+            opened_from text,
+            theme text,
+            foreign key (event_id, activity_type)
+                references activity (event_id, activity_type) on delete cascade,
+            unique (event_id, activity_type)
+        );
+        create table activity_notification_clicked(
+            id integer not null primary key autoincrement,
+            event_id text not null,
+            activity_type text not null,
+            notif_type text,
+            notif_user_id text,
+            message_id text,
+            channel_id text,
+            server_id text,
+            channel_type integer,
+            foreign key (event_id, activity_type)
+                references activity (event_id, activity_type) on delete cascade,
+            unique (event_id, activity_type)
+        );
+        create table blob_asset(
+            path text primary key not null,
+            mime_type text not null,
+            size integer not null,
+            hash text not null, -- sha256, hex encoded
+            content blob not null
+        );
+        create table import_error(
+            id integer not null primary key autoincrement,
+            path text not null,
+            line integer,
+            error text not null
+        );
+        create table package_manifest(
+            path text primary key not null,
+            format text, -- formats of data files, e.g. json+json.gz
+            layout text, -- current or legacy, for sections laid out differently in older packages
+            file_count integer not null,
+            known boolean not null -- false for folders the parser does not read
+        );
+        -- nullable columns are coalesced, nulls are never equal in unique constraints
+        -- newer packages sign cdn urls again, so cdn attachments are unique by their id
+        create unique index attachment_file
+            on attachment (message_id, coalesce(attachment_id, url));
+        create unique index server_settings_server
+            on server_settings (account_id, coalesce(server_id, ''));
+        create unique index support_ticket_comment_content
+            on support_ticket_comment (
+                ticket_id, coalesce(author, ''), coalesce(comment, ''), coalesce(created_at, '')
+            );
+        create view relationship_note as
+            select relationship.*, user_note.note
+            from relationship
+            left join user_note on user_note.user_id = relationship.user_id;
+        create view channel_recipient_note as
+            select channel_recipient.*, user_note.note
+            from channel_recipient
+            left join user_note on user_note.user_id = channel_recipient.recipient;
+        -- full-text index of message contents, rowids are message ids
+        create virtual table message_search using fts5(
+            contents,
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+        insert into message_search (rowid, contents)
+            select cast(id as integer), contents from message where contents is not null;
+        create trigger message_search_update after update of contents on message
+        when old.contents is not new.contents
+        begin
+            delete from message_search where rowid = cast(old.id as integer);
+            insert into message_search (rowid, contents)
+                select cast(new.id as integer), new.contents where new.contents is not null;
+        end;
+        create trigger message_search_delete after delete on message
+        begin
+            delete from message_search where rowid = cast(old.id as integer);
+        end;
+";
+
+/// Drops all tables, so the import starts from an empty db
+///
+/// child tables go first, so dropping a parent table has no rows to cascade to
+pub fn drop_tables() -> String {
+    "
+        drop view if exists relationship_note;
+        drop view if exists channel_recipient_note;
+        drop table if exists accepted_languages;
+        drop table if exists accepted_languages_weighted;
+        drop table if exists activity_session;
+        drop table if exists activity_send_message;
+        drop table if exists activity_voice;
+        drop table if exists activity_guild_viewed;
+        drop table if exists activity_app_opened;
+        drop table if exists activity_notification_clicked;
+        drop table if exists activity;
+        drop table if exists relationship;
+        drop table if exists user_note;
+        drop table if exists application_usage;
+        drop table if exists payment;
+        drop table if exists entitlement;
+        drop table if exists gift;
+        drop table if exists connection;
+        drop table if exists user_settings;
+        drop table if exists restricted_server;
+        drop table if exists server_settings;
+        drop table if exists channel_settings;
+        drop table if exists application_bot;
+        drop table if exists application;
+        drop table if exists account;
+        drop table if exists message_search;
+        drop table if exists attachment;
+        drop table if exists local_attachment;
+        drop table if exists message;
+        drop table if exists channel_recipient;
+        drop table if exists channel;
+        drop table if exists support_ticket_comment;
+        drop table if exists support_ticket;
+        drop table if exists audit_log;
+        drop table if exists server;
+        drop table if exists blob_asset;
+        drop table if exists import_error;
+        drop table if exists package_manifest;
+        drop table if exists schema_version;
     "
     .to_string()
 }
+
+/// Upgrades the db to `SCHEMA_VERSION`, a failed migration is rolled back
+///
+/// dbs written before schema versions were tracked have the initial schema
+pub fn migrate(connection: &Connection) -> BoxErrorResult<()> {
+    connection.execute_batch(
+        "
+        create table if not exists schema_version(
+            version integer primary key not null,
+            applied_at text not null
+        );
+        ",
+    )?;
+    let mut version = schema_version(connection)?;
+    if version == 0 && table_exists(connection, "account")? {
+        version = 1;
+        connection.execute(
+            "insert into schema_version values (?1, datetime('now'));",
+            (version,),
+        )?;
+    }
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "db schema version {} is newer than supported version {}",
+            version, SCHEMA_VERSION
+        )
+        .into());
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        // a savepoint works both on its own and inside the import transaction
+        connection.execute_batch("savepoint migration;")?;
+        let migrated = connection.execute_batch(migration).and_then(|_| {
+            connection.execute(
+                "insert into schema_version values (?1, datetime('now'));",
+                (index + 1,),
+            )
+        });
+        if let Err(err) = migrated {
+            connection.execute_batch("rollback to migration; release migration;")?;
+            return Err(format!("migration to schema version {}: {}", index + 1, err).into());
+        }
+        connection.execute_batch("release migration;")?;
+    }
+    Ok(())
+}
+
+/// Latest applied migration, 0 for an empty db
+fn schema_version(connection: &Connection) -> rusqlite::Result<usize> {
+    connection.query_row(
+        "select coalesce(max(version), 0) from schema_version;",
+        (),
+        |row| row.get(0),
+    )
+}
+
+fn table_exists(connection: &Connection, table: &str) -> rusqlite::Result<bool> {
+    connection.query_row(
+        "select count(*) > 0 from sqlite_master where type = 'table' and name = ?1;",
+        (table,),
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Db written by a release before schema versions were tracked, with the initial schema
+    fn unversioned_db() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(INITIAL_SCHEMA).unwrap();
+        connection
+            .execute_batch(
+                "
+                insert into account values (
+                    '100', 'me', 1, 'me@example.com', true, 'avatar', false, false,
+                    null, 0, null, null, '127.0.0.1', null, null
+                );
+                insert into relationship (
+                    account_id, relation_type, nickname, username, avatar,
+                    avatar_decoration, discriminator, public_flags
+                ) values ('100', 1, null, 'friend', null, null, '0001', 0);
+                insert into server values ('300', 'server');
+                insert into channel values ('400', 0, '300'), ('401', 1, null);
+                insert into channel_recipient (channel_id, recipient) values ('401', '100');
+                insert into message values
+                    ('1000', '400', '2021-01-01 00:00:00', 'hello world', null),
+                    ('1001', '401', '2021-01-02 00:00:00', null, 'https://example.com/a.png');
+                insert into activity (
+                    event_id, event_type, activity_type, user_id, domain,
+                    client_send_timestamp, client_track_timestamp, timestamp, other
+                ) values
+                    ('event0', 'app_opened', 'Analytics', '100', 'd', 't', 't', 't', '{}'),
+                    ('event0', 'app_opened', 'Reporting', '100', 'd', 't', 't', 't', '{}'),
+                    ('event1', 'app_opened', 'Analytics', '100', 'd', 't', 't', 't', '{}');
+                -- saved once for every category of the event
+                insert into accepted_languages (event_id, language) values
+                    ('event0', 'en'), ('event0', 'en'), ('event1', 'en'), ('event1', 'de');
+                insert into accepted_languages_weighted (event_id, language) values
+                    ('event0', 'en'), ('event0', 'en'), ('event1', 'en');
+                ",
+            )
+            .unwrap();
+        connection
+    }
+
+    fn count(connection: &Connection, sql: &str) -> u64 {
+        connection.query_row(sql, (), |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn upgrades_unversioned_db_in_place() {
+        let connection = unversioned_db();
+        // rebuilt parent tables would delete their child rows otherwise, as in `Dao::begin_import`
+        connection
+            .pragma_update(None, "foreign_keys", false)
+            .unwrap();
+
+        migrate(&connection).unwrap();
+
+        assert_eq!(schema_version(&connection).unwrap(), SCHEMA_VERSION);
+        let rows = [
+            ("account", 1),
+            ("relationship", 1),
+            ("server", 1),
+            ("channel", 2),
+            ("channel_recipient", 1),
+            ("message", 2),
+            ("activity", 3),
+            ("accepted_languages", 4),
+            ("accepted_languages_weighted", 3),
+        ];
+        for (table, expected) in rows {
+            let sql = format!("select count(*) from {};", table);
+            assert_eq!(count(&connection, &sql), expected, "rows of {}", table);
+        }
+        assert_eq!(
+            count(
+                &connection,
+                "select count(*) from pragma_foreign_key_check;"
+            ),
+            0
+        );
+        assert_eq!(
+            count(
+                &connection,
+                "select count(*) from relationship where user_id is null;"
+            ),
+            1
+        );
+        // events listed in several categories keep a row and their languages in each of them
+        assert_eq!(
+            count(
+                &connection,
+                "
+                select count(*) from accepted_languages
+                where event_id = 'event0' and activity_type in ('Analytics', 'Reporting');
+                "
+            ),
+            2
+        );
+        assert_eq!(
+            count(
+                &connection,
+                "select rowid from message_search where message_search match 'hello';"
+            ),
+            1000
+        );
+    }
+
+    #[test]
+    fn migrates_once() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        migrate(&connection).unwrap();
+
+        assert_eq!(schema_version(&connection).unwrap(), SCHEMA_VERSION);
+        assert_eq!(
+            count(&connection, "select count(*) from schema_version;"),
+            SCHEMA_VERSION as u64
+        );
+    }
+
+    #[test]
+    fn refuses_newer_db() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        connection
+            .execute(
+                "insert into schema_version values (?1, datetime('now'));",
+                (SCHEMA_VERSION + 1,),
+            )
+            .unwrap();
+
+        assert!(migrate(&connection).is_err());
+    }
+}
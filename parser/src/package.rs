@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use zip::ZipArchive;
+
+type BoxErrorResult<T> = Result<T, Box<dyn Error>>;
+type Archive = ZipArchive<BufReader<File>>;
+
+/// Discord data package
+///
+/// can be read from:
+/// - unzipped folder
+/// - package.zip as downloaded from discord
+///
+/// all paths are relative to the package root and use `/` as separator
+pub enum Package {
+    Folder(PathBuf),
+    /// zip path, opened archives, one per thread reading the package at the same time,
+    /// and names of all archive entries, listed once as the archive has no directory index
+    Zip(PathBuf, Mutex<Vec<Archive>>, BTreeSet<String>),
+}
+
+impl Package {
+    pub fn open(path: &str) -> BoxErrorResult<Self> {
+        let path = Path::new(path);
+        if path.is_dir() {
+            return Ok(Package::Folder(path.to_path_buf()));
+        }
+        let archive = open_archive(path)?;
+        let entries = archive.file_names().map(|name| name.to_string()).collect();
+        Ok(Package::Zip(
+            path.to_path_buf(),
+            Mutex::new(vec![archive]),
+            entries,
+        ))
+    }
+}
+
+impl Package {
+    /// Opens the file at `path` and passes its reader to `read`
+    ///
+    /// zip entries borrow the archive, so the reader is only available inside the closure
+    pub fn read<T>(
+        &self,
+        path: &str,
+        read: impl FnOnce(&mut dyn BufRead) -> BoxErrorResult<T>,
+    ) -> BoxErrorResult<T> {
+        match self {
+            Package::Folder(root) => {
+                let mut reader = BufReader::new(
+                    File::open(root.join(path)).map_err(|err| format!("{}: {}", path, err))?,
+                );
+                read(&mut reader)
+            }
+            Package::Zip(zip_path, archives, _entries) => {
+                with_archive(zip_path, archives, |archive| {
+                    let mut reader = BufReader::new(
+                        archive
+                            .by_name(path)
+                            .map_err(|err| format!("{}: {}", path, err))?,
+                    );
+                    read(&mut reader)
+                })
+            }
+        }
+    }
+
+    /// Lists names of files and folders placed directly inside `path`, sorted by name
+    pub fn list(&self, path: &str) -> BoxErrorResult<Vec<String>> {
+        match self {
+            Package::Folder(root) => {
+                let mut names = fs::read_dir(root.join(path))
+                    .map_err(|err| format!("{}: {}", path, err))?
+                    .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+                    .collect::<BoxErrorResult<Vec<String>>>()?;
+                names.sort();
+                Ok(names)
+            }
+            Package::Zip(_zip_path, _archives, entries) => {
+                let prefix = match path.trim_end_matches('/') {
+                    "" => String::new(),
+                    path => format!("{}/", path),
+                };
+                // entries are sorted, so the ones inside `path` follow each other
+                let names: BTreeSet<String> = entries
+                    .range(prefix.clone()..)
+                    .map_while(|name| name.strip_prefix(&prefix))
+                    .filter_map(|name| name.split('/').next())
+                    .filter(|name| !name.is_empty())
+                    .map(|name| name.to_string())
+                    .collect();
+                if names.is_empty() {
+                    return Err(format!("{}: not found in archive", path).into());
+                }
+                Ok(names.into_iter().collect())
+            }
+        }
+    }
+}
+
+impl Package {
+    /// Lists paths of all files in the package, sorted by path
+    pub fn files(&self) -> BoxErrorResult<Vec<String>> {
+        match self {
+            Package::Folder(root) => {
+                let mut files = Vec::new();
+                let mut folders = vec![String::new()];
+                while let Some(folder) = folders.pop() {
+                    for entry in fs::read_dir(root.join(&folder))? {
+                        let entry = entry?;
+                        let name = entry.file_name().to_string_lossy().to_string();
+                        let path = match folder.as_str() {
+                            "" => name,
+                            folder => format!("{}/{}", folder, name),
+                        };
+                        if entry.file_type()?.is_dir() {
+                            folders.push(path);
+                        } else {
+                            files.push(path);
+                        }
+                    }
+                }
+                files.sort();
+                Ok(files)
+            }
+            Package::Zip(_zip_path, _archives, entries) => Ok(entries
+                .iter()
+                .filter(|name| !name.ends_with('/'))
+                .cloned()
+                .collect()),
+        }
+    }
+}
+
+fn open_archive(path: &Path) -> BoxErrorResult<Archive> {
+    Ok(ZipArchive::new(BufReader::new(File::open(path)?))?)
+}
+
+/// Takes an idle archive, or opens one more if all of them are in use by other threads
+fn with_archive<T>(
+    zip_path: &Path,
+    archives: &Mutex<Vec<Archive>>,
+    use_archive: impl FnOnce(&mut Archive) -> BoxErrorResult<T>,
+) -> BoxErrorResult<T> {
+    let idle = archives.lock().map_err(|err| err.to_string())?.pop();
+    let mut archive = match idle {
+        Some(archive) => archive,
+        None => open_archive(zip_path)?,
+    };
+    let result = use_archive(&mut archive);
+    archives
+        .lock()
+        .map_err(|err| err.to_string())?
+        .push(archive);
+    result
+}
@@ -1,163 +1,694 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet},
     error::Error,
-    fs::{self, File},
+    fmt,
     io::{BufRead, BufReader},
+    marker::PhantomData,
+    num::NonZeroUsize,
+    sync::{
+        mpsc::{self, SyncSender},
+        Mutex,
+    },
+    thread,
 };
 
+use flate2::bufread::GzDecoder;
 use indicatif::ProgressBar;
-use serde::Deserialize;
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::Value;
 
 use crate::{
-    account::Account,
+    account::{Account, Application},
     activity::{Activity, ActivityType},
+    asset::{self, BlobAsset},
+    attachment::{AttachmentUrl, LocalAttachment, LocalAttachmentIndex},
     channel::{Channel, Message},
-    servers::Server,
+    manifest::PackageManifest,
+    package::Package,
+    servers::{AuditLogEntry, Server},
+    support::SupportTicket,
 };
 
 type BoxErrorResult<T> = Result<T, Box<dyn Error>>;
 
-pub struct ParserResult {
-    pub account: Account,
-    pub activities: HashMap<ActivityType, Vec<Activity>>,
-    pub channels: Vec<Channel>,
-    pub servers: Vec<Server>,
+/// Max count of messages or activities sent to the db in one event
+const CHUNK_SIZE: usize = 10_000;
+/// Max count of chunks a worker parses ahead of the db
+const PENDING_CHUNKS: usize = 2;
+const ACCOUNT_PATH: &str = "account/user.json";
+
+/// Parsed data, sent to the db while the package is still being read
+///
+/// messages and activities are split in chunks of `CHUNK_SIZE`,
+/// so memory usage does not depend on the package size
+pub enum ParserEvent {
+    Manifest(PackageManifest),
+    Account(Box<Account>),
+    Servers(Vec<Server>),
+    AuditLog(String, Vec<AuditLogEntry>),
+    Channel(Channel),
+    SupportTicket(SupportTicket),
+    Messages(String, Vec<Message>),
+    LocalAttachments(Vec<LocalAttachment>),
+    Activities(ActivityType, Vec<Activity>),
+    BlobAsset(BlobAsset),
+    ImportError(ImportError),
+}
+
+/// Broken file or record, skipped in lenient mode
+///
+/// `line` is the line of a csv or activity record, the number of a messages.json array element,
+/// counted from 1, and `None` for errors of the whole file
+pub struct ImportError {
+    pub path: String,
+    pub line: Option<u64>,
+    pub error: String,
+}
+
+pub struct ParserOptions {
+    /// skip broken files and records, reporting them as `ParserEvent::ImportError`
+    pub lenient: bool,
+    /// folder with downloaded message attachments
+    pub attachments_path: Option<String>,
+    /// save content of local attachments into the db instead of their path only
+    pub embed_attachments: bool,
 }
 
 pub struct Parser {
-    discord_data_path: String,
+    package: Package,
+    manifest: PackageManifest,
+    lenient: bool,
+    local_attachments: Option<LocalAttachmentIndex>,
+    embed_attachments: bool,
 }
 
 impl Parser {
-    pub fn new(discord_data_path: &str) -> Self {
-        Parser {
-            discord_data_path: discord_data_path.to_string(),
-        }
+    pub fn new(discord_data_path: &str, options: ParserOptions) -> BoxErrorResult<Self> {
+        let local_attachments = match options.attachments_path {
+            Some(attachments_path) => Some(
+                LocalAttachmentIndex::new(&attachments_path)
+                    .map_err(|err| format!("{}: {}", attachments_path, err))?,
+            ),
+            None => None,
+        };
+        let package = Package::open(discord_data_path)?;
+        let manifest = PackageManifest::detect(&package.files()?);
+        Ok(Parser {
+            package,
+            manifest,
+            lenient: options.lenient,
+            local_attachments,
+            embed_attachments: options.embed_attachments,
+        })
     }
 }
 
 impl Parser {
-    pub fn parse(&self, prog_bar: &ProgressBar) -> Result<ParserResult, Box<dyn Error>> {
-        let account = self.read_account()?;
+    pub fn parse(
+        &self,
+        sender: &SyncSender<ParserEvent>,
+        prog_bar: &ProgressBar,
+    ) -> BoxErrorResult<()> {
+        send(sender, ParserEvent::Manifest(self.manifest.clone()))?;
+        if self.manifest.has("account") {
+            let account = self.recover(self.read_account(), ACCOUNT_PATH, None, sender)?;
+            if let Some(mut account) = account {
+                account.applications = self.read_applications(sender)?;
+                send(sender, ParserEvent::Account(Box::new(account)))?;
+            }
+        }
         prog_bar.inc(1);
-        let servers = self.read_servers()?;
+        if self.manifest.has("servers") {
+            let servers = self.read_servers(sender)?;
+            let server_ids: Vec<String> =
+                servers.iter().map(|server| server.id.to_string()).collect();
+            send(sender, ParserEvent::Servers(servers))?;
+            self.read_audit_logs(&server_ids, sender)?;
+        }
         prog_bar.inc(1);
-        let channels = self.read_channels()?;
+        if self.manifest.has("messages") {
+            self.read_channels(sender)?;
+        }
+        if self.manifest.has("support_tickets") {
+            self.read_support_tickets(sender)?;
+        }
         prog_bar.inc(1);
-        let activities = self.read_all_activities()?;
+        self.read_all_activities(sender)?;
+        self.read_blob_assets(sender)?;
         prog_bar.inc(1);
-        Ok(ParserResult {
-            account,
-            activities,
-            channels,
-            servers,
-        })
+        Ok(())
+    }
+
+    /// Returns `result` as is in strict mode
+    ///
+    /// in lenient mode an error is sent as `ParserEvent::ImportError`
+    /// and `None` is returned, so the caller can skip the broken file or record
+    fn recover<T>(
+        &self,
+        result: BoxErrorResult<T>,
+        path: &str,
+        line: Option<u64>,
+        sender: &SyncSender<ParserEvent>,
+    ) -> BoxErrorResult<Option<T>> {
+        match result {
+            Ok(val) => Ok(Some(val)),
+            Err(err) if self.lenient => {
+                send(
+                    sender,
+                    ParserEvent::ImportError(ImportError {
+                        path: path.to_string(),
+                        line,
+                        error: err.to_string(),
+                    }),
+                )?;
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
     }
 
     fn read_account(&self) -> BoxErrorResult<Account> {
-        let account_reader = BufReader::new(File::open(format!(
-            "{}/account/user.json",
-            self.discord_data_path
-        ))?);
-        let account: Account = serde_json::from_reader(account_reader)?;
-        Ok(account)
+        self.package.read(ACCOUNT_PATH, |account_reader| {
+            let account: Account = serde_json::from_reader(account_reader)?;
+            Ok(account)
+        })
     }
 
-    fn read_servers(&self) -> BoxErrorResult<Vec<Server>> {
-        let servers_index =
-            File::open(format!("{}/servers/index.json", self.discord_data_path)).unwrap();
-        let index_reader = BufReader::new(servers_index);
-        let index: HashMap<String, String> = serde_json::from_reader(index_reader)?;
+    fn read_applications(
+        &self,
+        sender: &SyncSender<ParserEvent>,
+    ) -> BoxErrorResult<Vec<Application>> {
+        if !self.manifest.has("account/applications") {
+            return Ok(Vec::new());
+        }
 
-        index
-            .iter()
-            .map(|(key, _val)| -> BoxErrorResult<Server> {
-                let server_reader = BufReader::new(File::open(format!(
-                    "{}/servers/{}/guild.json",
-                    self.discord_data_path, key
-                ))?);
+        let mut applications = Vec::new();
+        for application_id in self.package.list("account/applications")? {
+            let application_path =
+                format!("account/applications/{}/application.json", application_id);
+            let application = self.package.read(&application_path, |application_reader| {
+                let application: Application = serde_json::from_reader(application_reader)?;
+                Ok(application)
+            });
+            if let Some(application) = self.recover(application, &application_path, None, sender)? {
+                applications.push(application);
+            }
+        }
+        Ok(applications)
+    }
+
+    /// Reads servers listed in servers/index.json
+    ///
+    /// legacy packages have no index, then every server folder is read
+    fn read_servers(&self, sender: &SyncSender<ParserEvent>) -> BoxErrorResult<Vec<Server>> {
+        let server_files = self.package.list("servers")?;
+        let server_ids: Vec<String> = if server_files.iter().any(|file| file == "index.json") {
+            let index_path = "servers/index.json";
+            let index: BoxErrorResult<BTreeMap<String, String>> =
+                self.package.read(index_path, |index_reader| {
+                    Ok(serde_json::from_reader(index_reader)?)
+                });
+            let Some(index) = self.recover(index, index_path, None, sender)? else {
+                return Ok(Vec::new());
+            };
+            index.into_keys().collect()
+        } else {
+            server_files
+                .into_iter()
+                .filter(|file| !file.contains('.'))
+                .collect()
+        };
+
+        let mut servers = Vec::new();
+        for server_id in server_ids {
+            let server_path = format!("servers/{}/guild.json", server_id);
+            let server = self.package.read(&server_path, |server_reader| {
                 let result: Server = serde_json::from_reader(server_reader)?;
                 Ok(result)
-            })
-            .collect::<BoxErrorResult<Vec<Server>>>()
+            });
+            if let Some(server) = self.recover(server, &server_path, None, sender)? {
+                servers.push(server);
+            }
+        }
+        Ok(servers)
     }
 
-    fn read_channels(&self) -> BoxErrorResult<Vec<Channel>> {
-        let index_reader = BufReader::new(File::open(format!(
-            "{}/messages/index.json",
-            self.discord_data_path
-        ))?);
-        let index: HashMap<String, Option<String>> =
-            serde_json::from_reader(index_reader).map_err(|err| err.to_string())?;
+    /// Reads audit logs, packages only have them for servers the account owns or moderates
+    fn read_audit_logs(
+        &self,
+        server_ids: &[String],
+        sender: &SyncSender<ParserEvent>,
+    ) -> BoxErrorResult<()> {
+        for server_id in server_ids {
+            let server_path = format!("servers/{}", server_id);
+            let server_files = self.package.list(&server_path);
+            let Some(server_files) = self.recover(server_files, &server_path, None, sender)? else {
+                continue;
+            };
+            if !server_files.iter().any(|file| file == "audit-log.json") {
+                continue;
+            }
 
-        index
-            .iter()
-            .map(|(key, val)| -> BoxErrorResult<Channel> {
-                let channel_path = format!("{}/messages/c{}/", self.discord_data_path, key);
-                let channel_reader =
-                    BufReader::new(File::open(format!("{}/channel.json", channel_path))?);
-                let channel_info: ChannelInfo = serde_json::from_reader(channel_reader)?;
-                let message_reader =
-                    BufReader::new(File::open(format!("{}/messages.csv", channel_path))?);
-                let mut rdr = csv::Reader::from_reader(message_reader);
-                let messages: Result<Vec<Message>, String> = rdr
-                    .deserialize()
-                    .map(|result| result.map_err(|err| err.to_string()))
-                    .collect();
-
-                let messages = messages?;
-
-                Ok(Channel {
-                    id: channel_info.id,
-                    name: val.to_owned(),
-                    channel_type: channel_info.channel_type,
-                    recipients: channel_info.recipients,
-                    guild: channel_info.guild,
-                    messages,
+            let audit_log_path = format!("{}/audit-log.json", server_path);
+            let audit_log = self.package.read(&audit_log_path, |audit_log_reader| {
+                let audit_log: Vec<AuditLogEntry> = serde_json::from_reader(audit_log_reader)?;
+                Ok(audit_log)
+            });
+            if let Some(audit_log) = self.recover(audit_log, &audit_log_path, None, sender)? {
+                send(
+                    sender,
+                    ParserEvent::AuditLog(server_id.to_string(), audit_log),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads channels listed in messages/index.json, with their names
+    ///
+    /// legacy packages name channel folders `<id>` instead of `c<id>`,
+    /// and some have no index, then every channel folder is read without a name
+    fn read_channels(&self, sender: &SyncSender<ParserEvent>) -> BoxErrorResult<()> {
+        let channel_folders: BTreeSet<String> =
+            self.package.list("messages")?.into_iter().collect();
+        let index: Vec<(String, Option<String>)> = if channel_folders.contains("index.json") {
+            let index_path = "messages/index.json";
+            let index: BoxErrorResult<BTreeMap<String, Option<String>>> =
+                self.package.read(index_path, |index_reader| {
+                    Ok(serde_json::from_reader(index_reader).map_err(|err| err.to_string())?)
+                });
+            let Some(index) = self.recover(index, index_path, None, sender)? else {
+                return Ok(());
+            };
+            index.into_iter().collect()
+        } else {
+            channel_folders
+                .iter()
+                .filter(|folder| !folder.contains('.'))
+                .map(|folder| {
+                    let channel_id = folder.strip_prefix('c').unwrap_or(folder);
+                    (channel_id.to_string(), None)
                 })
-            })
-            .collect::<BoxErrorResult<Vec<Channel>>>()
+                .collect()
+        };
+
+        run_ordered(&index, sender, |(key, val), sender| {
+            let channel_folder = match channel_folders.contains(&format!("c{}", key)) {
+                true => format!("c{}", key),
+                false => key.to_string(),
+            };
+            let channel_path = format!("messages/{}", channel_folder);
+            let channel = self.read_channel(&channel_path, val, sender);
+            self.recover(channel, &channel_path, None, sender)?;
+            Ok(())
+        })
+    }
+
+    fn read_channel(
+        &self,
+        channel_path: &str,
+        name: &Option<String>,
+        sender: &SyncSender<ParserEvent>,
+    ) -> BoxErrorResult<()> {
+        let channel_info: ChannelInfo = self.package.read(
+            &format!("{}/channel.json", channel_path),
+            |channel_reader| Ok(serde_json::from_reader(channel_reader)?),
+        )?;
+        let channel_id = channel_info.id.to_string();
+
+        send(
+            sender,
+            ParserEvent::Channel(Channel {
+                id: channel_info.id,
+                name: name.to_owned(),
+                channel_type: channel_info.channel_type,
+                recipients: channel_info.recipients,
+                guild: channel_info.guild,
+            }),
+        )?;
+        self.read_messages(channel_path, &channel_id, sender)
+    }
+
+    fn read_messages(
+        &self,
+        channel_path: &str,
+        channel_id: &str,
+        sender: &SyncSender<ParserEvent>,
+    ) -> BoxErrorResult<()> {
+        let send_messages = |messages: Vec<Message>| -> BoxErrorResult<()> {
+            let local_attachments = self.read_local_attachments(&messages, sender)?;
+            send(
+                sender,
+                ParserEvent::Messages(channel_id.to_string(), messages),
+            )?;
+            if !local_attachments.is_empty() {
+                send(sender, ParserEvent::LocalAttachments(local_attachments))?;
+            }
+            Ok(())
+        };
+
+        let channel_files = self.package.list(channel_path)?;
+        if channel_files.iter().any(|file| file == "messages.json") {
+            let messages_path = format!("{}/messages.json", channel_path);
+            return self.package.read(&messages_path, |message_reader| {
+                let mut deserializer = serde_json::Deserializer::from_reader(message_reader);
+                if self.lenient {
+                    // broken elements are skipped, so they are deserialized one by one.
+                    // values carry no position, the line of an error is the element number
+                    let mut element = 0;
+                    deserializer.deserialize_seq(ChunkedSeq {
+                        on_chunk: |values: Vec<Value>| {
+                            let mut messages = Vec::with_capacity(values.len());
+                            for value in values {
+                                element += 1;
+                                let message = Message::deserialize(value)
+                                    .map_err(|err| format!("element {}: {}", element, err).into());
+                                if let Some(message) =
+                                    self.recover(message, &messages_path, Some(element), sender)?
+                                {
+                                    messages.push(message);
+                                }
+                            }
+                            send_messages(messages)
+                        },
+                        marker: PhantomData,
+                    })?;
+                } else {
+                    deserializer.deserialize_seq(ChunkedSeq {
+                        on_chunk: &send_messages,
+                        marker: PhantomData,
+                    })?;
+                }
+                deserializer.end()?;
+                Ok(())
+            });
+        }
+
+        let messages_path = format!("{}/messages.csv", channel_path);
+        self.package.read(&messages_path, |message_reader| {
+            let mut rdr = csv::Reader::from_reader(message_reader);
+            let mut messages = Vec::with_capacity(CHUNK_SIZE);
+            for message in rdr.deserialize() {
+                let message = match message {
+                    Err(err) if !matches!(err.kind(), csv::ErrorKind::Io(_)) => {
+                        let line = err.position().map(|position| position.line());
+                        self.recover(Err(err.to_string().into()), &messages_path, line, sender)?
+                    }
+                    message => Some(message.map_err(|err| err.to_string())?),
+                };
+                messages.extend(message);
+                if messages.len() == CHUNK_SIZE {
+                    send_messages(messages)?;
+                    messages = Vec::with_capacity(CHUNK_SIZE);
+                }
+            }
+            if !messages.is_empty() {
+                send_messages(messages)?;
+            }
+            Ok(())
+        })
     }
 
-    fn read_all_activities(&self) -> BoxErrorResult<HashMap<ActivityType, Vec<Activity>>> {
-        let mut activities = HashMap::new();
-        activities.insert(
+    /// Finds attachments of `messages` in the local attachment folder, if there is one
+    fn read_local_attachments(
+        &self,
+        messages: &[Message],
+        sender: &SyncSender<ParserEvent>,
+    ) -> BoxErrorResult<Vec<LocalAttachment>> {
+        let Some(local_attachments) = &self.local_attachments else {
+            return Ok(Vec::new());
+        };
+
+        let mut found = Vec::new();
+        for message in messages {
+            for url in message.attachment_urls() {
+                let Some(attachment_url) = AttachmentUrl::parse(url) else {
+                    continue;
+                };
+                let Some(path) = local_attachments.find(&attachment_url) else {
+                    continue;
+                };
+                let local_attachment = LocalAttachment::read(
+                    &message.id,
+                    &attachment_url,
+                    url,
+                    path,
+                    self.embed_attachments,
+                )
+                .map_err(|err| err.to_string().into());
+                let local_path = path.to_string_lossy();
+                if let Some(local_attachment) =
+                    self.recover(local_attachment, &local_path, None, sender)?
+                {
+                    found.push(local_attachment);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Reads tickets stored either as `<id>.json` or as `<id>/ticket.json`
+    fn read_support_tickets(&self, sender: &SyncSender<ParserEvent>) -> BoxErrorResult<()> {
+        for entry in self.package.list("support_tickets")? {
+            if entry == "index.json" {
+                continue;
+            }
+            let ticket_path = if entry.ends_with(".json") {
+                format!("support_tickets/{}", entry)
+            } else {
+                format!("support_tickets/{}/ticket.json", entry)
+            };
+            let ticket = self.package.read(&ticket_path, |ticket_reader| {
+                let ticket: SupportTicket = serde_json::from_reader(ticket_reader)?;
+                Ok(ticket)
+            });
+            if let Some(ticket) = self.recover(ticket, &ticket_path, None, sender)? {
+                send(sender, ParserEvent::SupportTicket(ticket))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads images and other binary files, so the db keeps them after the package is deleted
+    fn read_blob_assets(&self, sender: &SyncSender<ParserEvent>) -> BoxErrorResult<()> {
+        for path in self.package.files()? {
+            let Some(mime_type) = asset::mime_type(&path) else {
+                continue;
+            };
+            let blob_asset = self.package.read(&path, |asset_reader| {
+                Ok(BlobAsset::read(&path, mime_type, asset_reader)?)
+            });
+            if let Some(blob_asset) = self.recover(blob_asset, &path, None, sender)? {
+                send(sender, ParserEvent::BlobAsset(blob_asset))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the activity categories at the same time, their events are interleaved
+    ///
+    /// activities of different categories never conflict, so the db does not need them in order
+    fn read_all_activities(&self, sender: &SyncSender<ParserEvent>) -> BoxErrorResult<()> {
+        let activity_types: Vec<(ActivityType, String)> = [
             ActivityType::Analytics,
-            self.read_activities(ActivityType::Analytics)?,
-        );
-        activities.insert(
             ActivityType::Modeling,
-            self.read_activities(ActivityType::Modeling)?,
-        );
-        activities.insert(
             ActivityType::Reporting,
-            self.read_activities(ActivityType::Reporting)?,
-        );
-        activities.insert(ActivityType::Tns, self.read_activities(ActivityType::Tns)?);
-
-        Ok(activities)
-    }
-
-    fn read_activities(&self, activity_type: ActivityType) -> BoxErrorResult<Vec<Activity>> {
-        let mut activity_dir = fs::read_dir(format!(
-            "{}/activity/{}",
-            self.discord_data_path,
-            format!("{:?}", activity_type).to_lowercase()
-        ))
-        .map_err(|err| err.to_string())?;
-        let activity_entry = activity_dir.next().ok_or("activity not found")??;
-        let activity_path = activity_entry.path().to_string_lossy().to_string();
-        let mut activity_reader = BufReader::new(File::open(activity_path)?);
+            ActivityType::Tns,
+        ]
+        .into_iter()
+        .map(|activity_type| {
+            let activity_dir = format!("activity/{:?}", activity_type).to_lowercase();
+            (activity_type, activity_dir)
+        })
+        .filter(|(_activity_type, activity_dir)| self.manifest.has(activity_dir))
+        .collect();
+
+        run_interleaved(
+            &activity_types,
+            sender,
+            |(activity_type, activity_dir), sender| {
+                let activities = self.read_activities(*activity_type, activity_dir, sender);
+                self.recover(activities, activity_dir, None, sender)?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Reads every events shard of `activity_dir`, in order of shard name
+    ///
+    /// shards are either plain `.json` or gzipped `.json.gz` files with one event per line
+    fn read_activities(
+        &self,
+        activity_type: ActivityType,
+        activity_dir: &str,
+        sender: &SyncSender<ParserEvent>,
+    ) -> BoxErrorResult<()> {
+        let shards: Vec<String> = self
+            .package
+            .list(activity_dir)?
+            .into_iter()
+            .filter(|shard| shard.ends_with(".json") || shard.ends_with(".json.gz"))
+            .collect();
+        if shards.is_empty() {
+            return Err("activity not found".into());
+        }
+        for shard in shards {
+            let shard_path = format!("{}/{}", activity_dir, shard);
+            let activities = self.package.read(&shard_path, |shard_reader| {
+                if shard.ends_with(".gz") {
+                    let mut shard_reader = BufReader::new(GzDecoder::new(shard_reader));
+                    self.read_activity_shard(activity_type, &shard_path, &mut shard_reader, sender)
+                } else {
+                    self.read_activity_shard(activity_type, &shard_path, shard_reader, sender)
+                }
+            });
+            self.recover(activities, &shard_path, None, sender)?;
+        }
+        Ok(())
+    }
+
+    fn read_activity_shard(
+        &self,
+        activity_type: ActivityType,
+        shard_path: &str,
+        shard_reader: &mut dyn BufRead,
+        sender: &SyncSender<ParserEvent>,
+    ) -> BoxErrorResult<()> {
         let mut buf = String::new();
-        let mut activities: Vec<Activity> = Vec::new();
-        while let Ok(buf_len) = activity_reader.read_line(&mut buf) {
+        let mut line = 0;
+        let mut activities: Vec<Activity> = Vec::with_capacity(CHUNK_SIZE);
+        loop {
+            let buf_len = shard_reader
+                .read_line(&mut buf)
+                .map_err(|err| format!("{}:{}: {}", shard_path, line + 1, err))?;
             if buf_len == 0 {
                 break;
             }
-            activities.push(serde_json::from_str(&buf).map_err(|err| err.to_string())?);
+            line += 1;
+            let activity = serde_json::from_str(&buf)
+                .map(|mut activity: Activity| {
+                    activity.parse_typed();
+                    activity
+                })
+                .map_err(|err| err.to_string().into());
+            activities.extend(self.recover(activity, shard_path, Some(line), sender)?);
             buf.clear();
+            if activities.len() == CHUNK_SIZE {
+                send(sender, ParserEvent::Activities(activity_type, activities))?;
+                activities = Vec::with_capacity(CHUNK_SIZE);
+            }
+        }
+        if !activities.is_empty() {
+            send(sender, ParserEvent::Activities(activity_type, activities))?;
         }
-        Ok(activities)
+        Ok(())
+    }
+}
+
+fn send(sender: &SyncSender<ParserEvent>, event: ParserEvent) -> BoxErrorResult<()> {
+    sender.send(event).map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+/// Runs `job` for every item of `jobs` on a worker pool
+///
+/// every job gets its own bounded channel, and the events are forwarded to `sender`
+/// job after job, so the db receives them in the same order as a sequential run would.
+/// jobs are taken in order, so the job being forwarded is always running or finished
+fn run_ordered<J: Sync>(
+    jobs: &[J],
+    sender: &SyncSender<ParserEvent>,
+    job: impl Fn(&J, &SyncSender<ParserEvent>) -> BoxErrorResult<()> + Sync,
+) -> BoxErrorResult<()> {
+    let workers = thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+        .min(jobs.len());
+    let (job_senders, job_receivers): (Vec<_>, Vec<_>) = jobs
+        .iter()
+        .map(|_| mpsc::sync_channel(PENDING_CHUNKS))
+        .unzip();
+    let queue = Mutex::new(jobs.iter().zip(job_senders).enumerate());
+    let failures: Vec<Mutex<Option<String>>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let next = match queue.lock() {
+                    Ok(mut queue) => queue.next(),
+                    Err(_) => return,
+                };
+                let Some((index, (item, job_sender))) = next else {
+                    return;
+                };
+                if let Err(err) = job(item, &job_sender) {
+                    if let Ok(mut failure) = failures[index].lock() {
+                        *failure = Some(err.to_string());
+                    }
+                }
+            });
+        }
+
+        for (index, job_receiver) in job_receivers.into_iter().enumerate() {
+            for event in job_receiver {
+                send(sender, event)?;
+            }
+            if let Some(err) = failures[index]
+                .lock()
+                .map_err(|err| err.to_string())?
+                .take()
+            {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Runs `job` for every item of `jobs` on a worker pool, sending events to `sender` directly
+///
+/// events of different jobs are interleaved, so a long job doesn't hold back the others.
+/// after a failed job no new job is started, and the first error is returned
+fn run_interleaved<J: Sync>(
+    jobs: &[J],
+    sender: &SyncSender<ParserEvent>,
+    job: impl Fn(&J, &SyncSender<ParserEvent>) -> BoxErrorResult<()> + Sync,
+) -> BoxErrorResult<()> {
+    let workers = thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+        .min(jobs.len());
+    let queue = Mutex::new(jobs.iter());
+    let failure: Mutex<Option<String>> = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (queue, failure, job) = (&queue, &failure, &job);
+            scope.spawn(move || loop {
+                let next = match (queue.lock(), failure.lock()) {
+                    (Ok(mut queue), Ok(failure)) if failure.is_none() => queue.next(),
+                    _ => return,
+                };
+                let Some(item) = next else {
+                    return;
+                };
+                if let Err(err) = job(item, &sender) {
+                    if let Ok(mut failure) = failure.lock() {
+                        failure.get_or_insert(err.to_string());
+                    }
+                    return;
+                }
+            });
+        }
+    });
+
+    match failure.into_inner().map_err(|err| err.to_string())? {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
     }
 }
 
@@ -169,3 +700,136 @@ struct ChannelInfo {
     recipients: Option<Vec<String>>,
     guild: Option<Server>,
 }
+
+/// Visits json array elements one by one and passes them to `on_chunk` in chunks of `CHUNK_SIZE`
+struct ChunkedSeq<T, F> {
+    on_chunk: F,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T, F> Visitor<'de> for ChunkedSeq<T, F>
+where
+    T: Deserialize<'de>,
+    F: FnMut(Vec<T>) -> BoxErrorResult<()>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        while let Some(element) = seq.next_element()? {
+            chunk.push(element);
+            if chunk.len() == CHUNK_SIZE {
+                (self.on_chunk)(chunk).map_err(de::Error::custom)?;
+                chunk = Vec::with_capacity(CHUNK_SIZE);
+            }
+        }
+        if !chunk.is_empty() {
+            (self.on_chunk)(chunk).map_err(de::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const JOBS: u64 = 8;
+
+    /// Sends `count` events labeled with `job`, later jobs finish first
+    fn labeled_job(job: &u64, sender: &SyncSender<ParserEvent>, count: u64) -> BoxErrorResult<()> {
+        thread::sleep(Duration::from_millis((JOBS - job) * 2));
+        for line in 0..count {
+            send(
+                sender,
+                ParserEvent::ImportError(ImportError {
+                    path: format!("job{}", job),
+                    line: Some(line),
+                    error: String::new(),
+                }),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn labels(receiver: mpsc::Receiver<ParserEvent>) -> Vec<(String, Option<u64>)> {
+        receiver
+            .try_iter()
+            .map(|event| match event {
+                ParserEvent::ImportError(import_error) => (import_error.path, import_error.line),
+                _ => panic!("unexpected event"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forwards_events_in_job_order() {
+        let jobs: Vec<u64> = (0..JOBS).collect();
+        let (sender, receiver) = mpsc::sync_channel(100);
+
+        run_ordered(&jobs, &sender, |job, sender| labeled_job(job, sender, 3)).unwrap();
+
+        let expected: Vec<(String, Option<u64>)> = jobs
+            .iter()
+            .flat_map(|job| (0..3).map(move |line| (format!("job{}", job), Some(line))))
+            .collect();
+        assert_eq!(labels(receiver), expected);
+    }
+
+    #[test]
+    fn stops_at_failed_job() {
+        let jobs: Vec<u64> = (0..JOBS).collect();
+        let (sender, receiver) = mpsc::sync_channel(100);
+
+        let result = run_ordered(&jobs, &sender, |job, sender| {
+            labeled_job(job, sender, 1)?;
+            match job {
+                2 => Err("job2 failed".into()),
+                _ => Ok(()),
+            }
+        });
+
+        assert_eq!(result.unwrap_err().to_string(), "job2 failed");
+        let expected: Vec<(String, Option<u64>)> =
+            (0..3).map(|job| (format!("job{}", job), Some(0))).collect();
+        assert_eq!(labels(receiver), expected);
+    }
+
+    #[test]
+    fn sends_every_event_of_interleaved_jobs() {
+        let jobs: Vec<u64> = (0..JOBS).collect();
+        let (sender, receiver) = mpsc::sync_channel(100);
+
+        run_interleaved(&jobs, &sender, |job, sender| labeled_job(job, sender, 3)).unwrap();
+
+        let mut labels = labels(receiver);
+        labels.sort();
+        let expected: Vec<(String, Option<u64>)> = jobs
+            .iter()
+            .flat_map(|job| (0..3).map(move |line| (format!("job{}", job), Some(line))))
+            .collect();
+        assert_eq!(labels, expected);
+    }
+
+    #[test]
+    fn returns_failure_of_interleaved_job() {
+        let jobs: Vec<u64> = (0..JOBS).collect();
+        let (sender, _receiver) = mpsc::sync_channel(100);
+
+        let result = run_interleaved(&jobs, &sender, |job, sender| {
+            labeled_job(job, sender, 1)?;
+            match job {
+                2 => Err("job2 failed".into()),
+                _ => Ok(()),
+            }
+        });
+
+        assert_eq!(result.unwrap_err().to_string(), "job2 failed");
+    }
+}
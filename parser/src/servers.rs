@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use serde_json::Value;
 
 /// Server info
 /// 
@@ -9,3 +10,19 @@ pub struct Server {
     pub id: String,
     pub name: String,
 }
+
+/// Audit log entry
+///
+/// contains:
+/// - who did what with which target
+/// - changes and options, kept as json
+#[derive(Debug, Deserialize)]
+pub struct AuditLogEntry {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub target_id: Option<String>,
+    pub action_type: u32,
+    pub changes: Option<Value>,
+    pub options: Option<Value>,
+    pub reason: Option<String>,
+}
@@ -0,0 +1,33 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::channel::string_or_number;
+
+/// Support ticket
+///
+/// contains:
+/// - basic ticket info
+/// - comments
+#[derive(Debug, Deserialize)]
+pub struct SupportTicket {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub subject: Option<String>,
+    pub description: Option<String>,
+    pub status: Option<String>,
+    pub priority: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(default)]
+    pub comments: Vec<SupportTicketComment>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SupportTicketComment {
+    /// name or user object, depending on who wrote the comment
+    pub author: Option<Value>,
+    #[serde(alias = "body")]
+    pub comment: Option<String>,
+    pub public: Option<bool>,
+    pub created_at: Option<String>,
+}